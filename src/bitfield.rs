@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+/// One bit per chunk index, most significant bit first within each byte.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    len: u64,
+    bits: Vec<u8>,
+}
+
+impl Bitfield {
+    pub fn new(len: u64) -> Self {
+        Bitfield {
+            len,
+            bits: vec![0; len.div_ceil(8) as usize],
+        }
+    }
+
+    pub fn full(len: u64) -> Self {
+        let mut b = Bitfield::new(len);
+        for i in 0..len {
+            b.set(i);
+        }
+        b
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Out-of-range indices read as unset.
+    pub fn get(&self, index: u64) -> bool {
+        index < self.len && self.bits[(index / 8) as usize] & (0x80 >> (index % 8)) != 0
+    }
+
+    pub fn set(&mut self, index: u64) {
+        if index < self.len {
+            self.bits[(index / 8) as usize] |= 0x80 >> (index % 8);
+        }
+    }
+
+    pub fn unset(&mut self, index: u64) {
+        if index < self.len {
+            self.bits[(index / 8) as usize] &= !(0x80 >> (index % 8));
+        }
+    }
+
+    pub fn count_ones(&self) -> u64 {
+        self.bits.iter().map(|b| b.count_ones() as u64).sum()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count_ones() == self.len
+    }
+
+    pub fn iter_ones(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.len).filter(|&i| self.get(i))
+    }
+
+    /// Whether the encoded length and backing bytes agree, for bitfields
+    /// that came off the wire.
+    pub fn is_well_formed(&self) -> bool {
+        self.bits.len() as u64 == self.len.div_ceil(8)
+    }
+}
@@ -1,9 +1,67 @@
-use crate::error::Result;
+use crate::error::{Result, SyncError};
+use fastcdc::v2020::{self as cdc, StreamCDC};
+use serde::{Deserialize, Serialize};
 use std::{
-    fs::File,
+    fs::{self, File, OpenOptions},
     io::{BufReader, Read, Write},
-    path::Path,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
 };
+
+/// How a file is cut into chunks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Chunking {
+    /// Every chunk is `chunk_size` bytes except the last.
+    #[default]
+    Fixed,
+    /// Content-defined (FastCDC) cut points: chunks are `min` to `max`
+    /// bytes and about `avg` on average, and an insertion or deletion only
+    /// changes the chunks around it.
+    Cdc { min: u32, avg: u32, max: u32 },
+}
+
+impl Chunking {
+    /// Content-defined chunking around `avg` bytes, with the usual quarter
+    /// and fourfold bounds unless given.
+    pub fn cdc(avg: u32, min: Option<u32>, max: Option<u32>) -> Result<Self> {
+        let chunking = Chunking::Cdc {
+            min: min.unwrap_or(avg / 4),
+            avg,
+            max: max.unwrap_or(avg.saturating_mul(4)),
+        };
+        chunking.validate()?;
+        Ok(chunking)
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, Chunking::Fixed)
+    }
+
+    /// Checks the size policy against what the chunker supports.
+    pub fn validate(&self) -> Result<()> {
+        let Chunking::Cdc { min, avg, max } = *self else {
+            return Ok(());
+        };
+        let in_range = (cdc::MINIMUM_MIN..=cdc::MINIMUM_MAX).contains(&min)
+            && (cdc::AVERAGE_MIN..=cdc::AVERAGE_MAX).contains(&avg)
+            && (cdc::MAXIMUM_MIN..=cdc::MAXIMUM_MAX).contains(&max);
+        if !in_range || min > avg || avg > max {
+            return Err(SyncError::Other(format!(
+                "unsupported chunk size policy min={min} avg={avg} max={max} \
+                 (min {}..={}, avg {}..={}, max {}..={}, min <= avg <= max)",
+                cdc::MINIMUM_MIN,
+                cdc::MINIMUM_MAX,
+                cdc::AVERAGE_MIN,
+                cdc::AVERAGE_MAX,
+                cdc::MAXIMUM_MIN,
+                cdc::MAXIMUM_MAX
+            )));
+        }
+        Ok(())
+    }
+}
+
 pub struct Chunk {
     pub index: u64,
     pub hash: [u8; 32],
@@ -11,31 +69,90 @@ pub struct Chunk {
     pub size: usize,
 }
 
-pub fn split_file(path: &Path, chunk_size: usize) -> Result<Vec<Chunk>> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
+/// Reads a file one chunk at a time, so only the current chunk is held in
+/// memory. With [`Chunking::Fixed`] every chunk except the last is exactly
+/// `chunk_size` bytes.
+pub struct ChunkReader {
+    source: Source,
+    index: u64,
+}
+
+enum Source {
+    Fixed {
+        reader: BufReader<File>,
+        chunk_size: usize,
+        done: bool,
+    },
+    Cdc(StreamCDC<File>),
+}
 
-    let mut chunks = Vec::new();
-    let mut buffer = vec![0u8; chunk_size];
-    let mut index = 0;
+impl ChunkReader {
+    pub fn open(path: &Path, chunk_size: usize) -> Result<Self> {
+        Self::open_with(path, chunk_size, Chunking::Fixed)
+    }
 
-    loop {
-        let n = reader.read(&mut buffer)?;
-        if n == 0 {
-            break;
-        }
-        let data = buffer[..n].to_vec();
+    /// Like [`ChunkReader::open`], cutting chunks according to `chunking`.
+    pub fn open_with(path: &Path, chunk_size: usize, chunking: Chunking) -> Result<Self> {
+        chunking.validate()?;
+        let file = File::open(path)?;
+        let source = match chunking {
+            Chunking::Fixed => Source::Fixed {
+                reader: BufReader::new(file),
+                chunk_size,
+                done: false,
+            },
+            Chunking::Cdc { min, avg, max } => Source::Cdc(StreamCDC::new(file, min, avg, max)),
+        };
+        Ok(ChunkReader { source, index: 0 })
+    }
+}
+
+impl Iterator for ChunkReader {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = match &mut self.source {
+            Source::Fixed {
+                reader,
+                chunk_size,
+                done,
+            } => {
+                if *done {
+                    return None;
+                }
+                let mut data = Vec::with_capacity(*chunk_size);
+                if let Err(e) = reader.take(*chunk_size as u64).read_to_end(&mut data) {
+                    *done = true;
+                    return Some(Err(e.into()));
+                }
+                if data.len() < *chunk_size {
+                    *done = true;
+                }
+                if data.is_empty() {
+                    return None;
+                }
+                data
+            }
+            Source::Cdc(chunker) => match chunker.next()? {
+                Ok(c) => c.data,
+                Err(e) => return Some(Err(std::io::Error::from(e).into())),
+            },
+        };
         let hash: [u8; 32] = blake3::hash(&data).into();
-        chunks.push(Chunk {
-            index,
+        let chunk = Chunk {
+            index: self.index,
             hash,
+            size: data.len(),
             data,
-            size: n,
-        });
-        index += 1;
+        };
+        self.index += 1;
+        Some(Ok(chunk))
     }
+}
 
-    Ok(chunks)
+/// Reads the whole file into memory; prefer [`ChunkReader`] for large files.
+pub fn split_file(path: &Path, chunk_size: usize) -> Result<Vec<Chunk>> {
+    ChunkReader::open(path, chunk_size)?.collect()
 }
 
 pub fn combine_chunks(chunks: &[Chunk], output: &Path) -> Result<()> {
@@ -45,3 +162,105 @@ pub fn combine_chunks(chunks: &[Chunk], output: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+/// Output file that fetched chunks are written into at their final offsets.
+///
+/// Data goes to a sparse `<path>.part` file sized up front, so chunks can
+/// land in any order without being buffered; [`ChunkWriter::finish`] renames
+/// it into place once everything has arrived.
+pub struct ChunkWriter {
+    file: File,
+    part_path: PathBuf,
+    final_path: PathBuf,
+    /// Offset and length of each chunk.
+    spans: Vec<(u64, usize)>,
+}
+
+impl ChunkWriter {
+    pub fn part_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".part");
+        PathBuf::from(name)
+    }
+
+    /// Opens (or creates) the `.part` file for `path` without truncating it,
+    /// so an interrupted download keeps the chunks it already wrote.
+    pub fn create(path: &Path, file_size: u64, chunk_size: usize) -> Result<Self> {
+        if chunk_size == 0 && file_size > 0 {
+            return Err(SyncError::Other("chunk size must be positive".into()));
+        }
+        let spans = (0..file_size.div_ceil(chunk_size as u64))
+            .map(|i| {
+                let offset = i * chunk_size as u64;
+                (offset, (file_size - offset).min(chunk_size as u64) as usize)
+            })
+            .collect();
+        Self::with_spans(path, file_size, spans)
+    }
+
+    /// Like [`ChunkWriter::create`], for chunks of the given `(offset, len)`
+    /// spans, which must tile `file_size` exactly.
+    pub fn with_spans(path: &Path, file_size: u64, spans: Vec<(u64, usize)>) -> Result<Self> {
+        let mut end = 0;
+        for &(offset, len) in &spans {
+            if offset != end {
+                return Err(SyncError::Other(format!(
+                    "chunk layout has a gap or overlap at offset {offset}"
+                )));
+            }
+            end += len as u64;
+        }
+        if end != file_size {
+            return Err(SyncError::Other(format!(
+                "chunks cover {end} bytes but the file is {file_size}"
+            )));
+        }
+        let part_path = Self::part_path(path);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&part_path)?;
+        file.set_len(file_size)?;
+        Ok(ChunkWriter {
+            file,
+            part_path,
+            final_path: path.to_path_buf(),
+            spans,
+        })
+    }
+
+    fn span(&self, index: u64) -> Result<(u64, usize)> {
+        self.spans
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| SyncError::Other(format!("chunk {index} lies past the end of the file")))
+    }
+
+    pub fn write_chunk(&self, index: u64, data: &[u8]) -> Result<()> {
+        let (offset, len) = self.span(index)?;
+        if data.len() != len {
+            return Err(SyncError::Other(format!(
+                "chunk {index} is {} bytes, expected {len}",
+                data.len()
+            )));
+        }
+        self.file.write_all_at(data, offset)?;
+        Ok(())
+    }
+
+    pub fn read_chunk(&self, index: u64) -> Result<Vec<u8>> {
+        let (offset, len) = self.span(index)?;
+        let mut data = vec![0u8; len];
+        self.file.read_exact_at(&mut data, offset)?;
+        Ok(data)
+    }
+
+    /// Flushes the data and atomically renames the `.part` file into place.
+    pub fn finish(self) -> Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.part_path, &self.final_path)?;
+        Ok(())
+    }
+}
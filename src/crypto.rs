@@ -115,16 +115,26 @@ pub fn verify(pubkey_bytes: &[u8; 32], msg: &[u8], sig_bytes: &[u8; 64]) -> Resu
     Ok(verifying.verify_strict(msg, &sig).is_ok())
 }
 
-/// Building canonical handshake payload to sign:
-/// e.g. b"HANDSHAKE" || node_id_bytes || timestamp_be_bytes
-pub fn build_handshake_payload(node_id: &NodeId, timestamp_unix_secs: u64) -> Vec<u8> {
-    let mut out = Vec::with_capacity(16 + node_id.len() + 8);
-    out.extend_from_slice(b"HANDSHAKE");
-    out.extend_from_slice(node_id.as_bytes());
-    out.extend_from_slice(&timestamp_unix_secs.to_be_bytes());
+/// Canonical handshake payload to sign: `role` (which end is signing),
+/// keying material exported from the TLS session, and the other end's
+/// fresh challenge nonce.
+pub fn build_handshake_payload(role: &[u8], session: &[u8; 32], challenge: &[u8; 32]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(role.len() + 64);
+    out.extend_from_slice(role);
+    out.extend_from_slice(session);
+    out.extend_from_slice(challenge);
     out
 }
 
+/// 32 random bytes for a handshake challenge.
+pub fn random_nonce() -> Result<[u8; 32]> {
+    let mut nonce = [0u8; 32];
+    OsRng
+        .try_fill_bytes(&mut nonce)
+        .map_err(|e| crate::error::SyncError::Other(format!("RNG error: {}", e)))?;
+    Ok(nonce)
+}
+
 pub fn default_key_path() -> PathBuf {
     if let Some(mut dir) = dirs::config_dir() {
         dir.push("p2rent");
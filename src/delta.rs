@@ -0,0 +1,53 @@
+use crate::bitfield::Bitfield;
+use crate::chunk::{Chunk, ChunkWriter};
+use crate::error::Result;
+use crate::manifest::Manifest;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Copies every chunk of `manifest` that the local file `local` already
+/// contains into `writer`, matching by hash, and marks it in `completed`.
+///
+/// `local` is cut with the manifest's own chunking policy, so an older
+/// version of the file shares every chunk that did not change (with
+/// content-defined chunking, even after insertions). `on_chunk` sees each
+/// reused chunk once, e.g. to add it to the chunk store. Returns how many
+/// chunks of the manifest were filled in.
+pub fn reuse_local_chunks<F>(
+    manifest: &Manifest,
+    local: &Path,
+    writer: &ChunkWriter,
+    completed: &mut Bitfield,
+    mut on_chunk: F,
+) -> Result<u64>
+where
+    F: FnMut(&Chunk) -> Result<()>,
+{
+    let mut wanted: HashMap<[u8; 32], Vec<u64>> = HashMap::new();
+    for (index, hash) in manifest.chunks.iter().enumerate() {
+        if !completed.get(index as u64) {
+            wanted.entry(*hash).or_default().push(index as u64);
+        }
+    }
+    if wanted.is_empty() {
+        return Ok(0);
+    }
+
+    let mut reused = 0;
+    for chunk in manifest.chunk_reader(local)? {
+        let chunk = chunk?;
+        let Some(indices) = wanted.remove(&chunk.hash) else {
+            continue;
+        };
+        for &index in &indices {
+            writer.write_chunk(index, &chunk.data)?;
+            completed.set(index);
+        }
+        on_chunk(&chunk)?;
+        reused += indices.len() as u64;
+        if wanted.is_empty() {
+            break;
+        }
+    }
+    Ok(reused)
+}
@@ -32,6 +32,9 @@ pub enum SyncError {
     #[error("QUIC stream read-to-end error: {0}")]
     QuicReadToEnd(#[from] quinn::ReadToEndError),
 
+    #[error("QUIC stream read error: {0}")]
+    QuicReadExact(#[from] quinn::ReadExactError),
+
     #[error("QUIC stream was closed: {0}")]
     QuicStreamClosed(#[from] quinn::ClosedStream),
 
@@ -0,0 +1,239 @@
+use crate::chunk::{Chunk, ChunkWriter};
+use crate::delta;
+use crate::error::{Result, SyncError};
+use crate::manifest::{self, DirectoryManifest, Manifest};
+use crate::net::download::Downloader;
+use crate::net::quic::Peer;
+use crate::resume::{self, ResumeState};
+use crate::storage;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+/// Verified chunks between resume-record checkpoints during a fetch.
+pub const RESUME_SAVE_INTERVAL: u32 = 32;
+
+/// One file of a fetch and where it is written.
+pub struct FetchTarget {
+    pub share: String,
+    pub out_path: PathBuf,
+    /// Local file whose matching chunks are reused, if it exists.
+    pub seed_from: PathBuf,
+    pub manifest: Manifest,
+}
+
+/// Whether `path` already holds exactly the content `manifest` describes.
+pub fn is_complete(path: &Path, manifest: &Manifest) -> bool {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return false;
+    };
+    if meta.len() != manifest.file_size {
+        return false;
+    }
+    let Ok(reader) = manifest.chunk_reader(path) else {
+        return false;
+    };
+    let mut count = 0;
+    for (c, expected) in reader.zip(&manifest.chunks) {
+        match c {
+            Ok(c) if c.hash == *expected => count += 1,
+            _ => return false,
+        }
+    }
+    count == manifest.chunks.len()
+}
+
+/// Records the manifests of a fetch under `manifest_dir` so this node can
+/// serve what it downloads; written before downloading so partially
+/// downloaded chunks can already be announced to other peers.
+///
+/// Names come from the manifests, which may have come from a peer: any that
+/// would land outside `manifest_dir`, a directory whose entries do not match
+/// their share ids, and a share already recorded for different content are
+/// refused before anything is written.
+pub fn seed_manifests(
+    manifest_dir: &Path,
+    targets: &[FetchTarget],
+    tree: Option<&DirectoryManifest>,
+) -> Result<()> {
+    for t in targets {
+        if !manifest::is_safe_relative(&t.share) {
+            return Err(SyncError::Other(format!(
+                "refusing to seed share {:?}: not a safe relative name",
+                t.share
+            )));
+        }
+        manifest::check_replace(
+            &manifest::manifest_path(manifest_dir, &t.share),
+            &t.manifest,
+        )?;
+    }
+    if let Some(d) = tree {
+        if !manifest::is_safe_relative(&d.name) {
+            return Err(SyncError::Other(format!(
+                "refusing to seed directory {:?}: not a safe relative name",
+                d.name
+            )));
+        }
+        d.check_paths()?;
+        manifest::check_replace_directory(
+            &manifest::directory_manifest_path(manifest_dir, &d.name),
+            d,
+        )?;
+    }
+    for t in targets {
+        manifest::write_manifest(
+            &t.manifest,
+            &manifest::manifest_path(manifest_dir, &t.share),
+        )?;
+    }
+    if let Some(d) = tree {
+        manifest::write_directory_manifest(
+            d,
+            &manifest::directory_manifest_path(manifest_dir, &d.name),
+        )?;
+    }
+    Ok(())
+}
+
+/// A file being downloaded into `<out>.part`, picking up the chunks an
+/// interrupted attempt left there and any found in `seed_from`.
+pub struct FileFetch<'a> {
+    target: &'a FetchTarget,
+    seed_dir: Option<&'a Path>,
+    writer: ChunkWriter,
+    state: ResumeState,
+    resume_path: PathBuf,
+    /// Chunks copied from `seed_from` instead of being downloaded.
+    pub reused: u64,
+}
+
+impl<'a> FileFetch<'a> {
+    /// Prepares `target` for download, or `None` if its output already
+    /// holds the content. With `seed_dir` every verified chunk is also added
+    /// to that chunk store.
+    pub fn open(target: &'a FetchTarget, seed_dir: Option<&'a Path>) -> Result<Option<Self>> {
+        let FetchTarget {
+            out_path,
+            manifest: m,
+            ..
+        } = target;
+        if is_complete(out_path, m) {
+            return Ok(None);
+        }
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Verified chunks are written at their final offsets in
+        // `<out>.part` as they arrive, so memory use stays flat and an
+        // interrupted fetch can resume from the file itself.
+        let writer = m.chunk_writer(out_path)?;
+        let resume_path = resume::resume_path(out_path);
+        let mut state = match resume::load_resume(&resume_path)? {
+            Some(state) if state.matches(m) => state,
+            _ => ResumeState::new(m),
+        };
+        for index in state.completed.iter_ones().collect::<Vec<_>>() {
+            let intact = writer.read_chunk(index).is_ok_and(|data| {
+                <[u8; 32]>::from(blake3::hash(&data)) == m.chunks[index as usize]
+            });
+            if !intact {
+                state.completed.unset(index);
+            }
+        }
+        let mut reused = 0;
+        if target.seed_from.is_file() {
+            reused = delta::reuse_local_chunks(
+                m,
+                &target.seed_from,
+                &writer,
+                &mut state.completed,
+                |c| {
+                    if let Some(storage_dir) = seed_dir {
+                        storage::save_object(storage_dir, c)?;
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        resume::save_resume(&state, &resume_path)?;
+        Ok(Some(FileFetch {
+            target,
+            seed_dir,
+            writer,
+            state,
+            resume_path,
+            reused,
+        }))
+    }
+
+    /// Chunks already in the `.part` file, reused ones included.
+    pub fn on_disk(&self) -> u64 {
+        self.state.completed.count_ones()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.target.manifest.chunks.len() as u64
+    }
+
+    /// Downloads the missing chunks from `peers`, keeping `window` requests
+    /// in flight per peer, and moves the finished file into place.
+    /// `on_chunk` runs after each chunk is written. If the download fails or
+    /// `interrupt` completes first, the resume record is saved so opening
+    /// the same target again continues where this left off.
+    pub async fn download<I, F>(
+        mut self,
+        peers: &[Peer],
+        window: usize,
+        interrupt: I,
+        mut on_chunk: F,
+    ) -> Result<()>
+    where
+        I: Future<Output = ()>,
+        F: FnMut(),
+    {
+        let FetchTarget {
+            share, manifest: m, ..
+        } = self.target;
+        let (writer, state, resume_path) = (&self.writer, &mut self.state, &self.resume_path);
+        let mut since_save = 0;
+        let download = Downloader::new(peers.to_vec(), share.clone(), m)
+            .window(window)
+            .completed(state.completed.clone())
+            .run(|index, data| {
+                writer.write_chunk(index, &data)?;
+                if let Some(storage_dir) = self.seed_dir {
+                    let ch = Chunk {
+                        index,
+                        hash: m.chunks[index as usize],
+                        size: data.len(),
+                        data,
+                    };
+                    storage::save_object(storage_dir, &ch)?;
+                }
+                state.completed.set(index);
+                since_save += 1;
+                if since_save >= RESUME_SAVE_INTERVAL {
+                    resume::save_resume(state, resume_path)?;
+                    since_save = 0;
+                }
+                on_chunk();
+                Ok(())
+            });
+        let result = tokio::select! {
+            r = download => r,
+            _ = interrupt => Err(SyncError::Other("interrupted".into())),
+        };
+        if let Err(e) = result {
+            resume::save_resume(&self.state, &self.resume_path)?;
+            return Err(SyncError::Other(format!(
+                "{e}; fetch incomplete, rerun it to resume ({}/{} chunks of {} kept)",
+                self.on_disk(),
+                self.total(),
+                self.target.out_path.display()
+            )));
+        }
+        self.writer.finish()?;
+        std::fs::remove_file(&self.resume_path)?;
+        Ok(())
+    }
+}
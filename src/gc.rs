@@ -0,0 +1,158 @@
+use crate::error::{Result, SyncError};
+use crate::manifest::{self, Manifest};
+use crate::storage::StoreLock;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub manifests: usize,
+    pub referenced: usize,
+    /// Files deleted, or that would be deleted in a dry run.
+    pub removed: Vec<PathBuf>,
+    pub bytes_freed: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcOptions {
+    /// Only report what would be deleted.
+    pub dry_run: bool,
+    /// Delete even when there are no manifests, which empties the store.
+    pub allow_empty: bool,
+}
+
+/// Deletes everything in `storage_dir` that no manifest in `manifest_dir`
+/// refers to: unreferenced objects and their outboards, any other file in
+/// an object bucket (such as leftover temporary files), and `*.chunk` files
+/// of the older per-stem layout whose manifest is gone or shorter. Anything
+/// else in the store is left alone.
+///
+/// With `dry_run` nothing is deleted and the report lists what would be.
+/// Otherwise the store is locked exclusively, so this fails while `serve` or
+/// `share` is using it. A missing `manifest_dir` is an error, and so is
+/// deleting with no manifests at all unless `allow_empty` is set: both
+/// usually mean the wrong directory was given.
+pub fn collect_garbage(
+    storage_dir: &Path,
+    manifest_dir: &Path,
+    options: GcOptions,
+) -> Result<GcReport> {
+    let GcOptions {
+        dry_run,
+        allow_empty,
+    } = options;
+    if !manifest_dir.is_dir() {
+        return Err(SyncError::Other(format!(
+            "manifest directory {} does not exist",
+            manifest_dir.display()
+        )));
+    }
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(StoreLock::exclusive(storage_dir)?)
+    };
+
+    // Any unreadable manifest aborts the run rather than risk deleting the
+    // chunks it refers to.
+    let mut referenced = HashSet::new();
+    let mut legacy_lengths = HashMap::new();
+    let manifest_paths = manifest::list_manifests(manifest_dir)?;
+    for path in &manifest_paths {
+        let m: Manifest = manifest::read_manifest(path).map_err(|e| {
+            SyncError::Other(format!("cannot read manifest {}: {e}", path.display()))
+        })?;
+        if let Some(share) = path
+            .strip_prefix(manifest_dir)
+            .ok()
+            .and_then(|p| p.to_str())
+            .and_then(|p| p.strip_suffix(".manifest.json"))
+        {
+            legacy_lengths.insert(share.to_string(), m.chunks.len() as u64);
+        }
+        referenced.extend(m.chunks);
+    }
+    if manifest_paths.is_empty() && !dry_run && !allow_empty {
+        return Err(SyncError::Other(format!(
+            "no manifests in {}; collecting would delete every stored chunk",
+            manifest_dir.display()
+        )));
+    }
+
+    let mut report = GcReport {
+        manifests: manifest_paths.len(),
+        referenced: referenced.len(),
+        ..GcReport::default()
+    };
+    if !storage_dir.is_dir() {
+        return Ok(report);
+    }
+
+    let mut garbage = Vec::new();
+    for entry in fs::read_dir(storage_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == "objects" {
+            for bucket in fs::read_dir(&path)? {
+                let bucket = bucket?;
+                if !bucket.file_type()?.is_dir() {
+                    continue;
+                }
+                for object in fs::read_dir(bucket.path())? {
+                    let object = object?;
+                    if !object.file_type()?.is_file() {
+                        continue;
+                    }
+                    let object = object.path();
+                    let keep = object
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| n.strip_suffix(".obao").unwrap_or(n))
+                        .and_then(|n| blake3::Hash::from_hex(n).ok())
+                        .is_some_and(|h| referenced.contains(h.as_bytes()));
+                    if !keep {
+                        garbage.push(object);
+                    }
+                }
+            }
+        } else {
+            let len = legacy_lengths.get(name.as_ref()).copied().unwrap_or(0);
+            for chunk in fs::read_dir(&path)? {
+                let chunk = chunk?;
+                if !chunk.file_type()?.is_file() {
+                    continue;
+                }
+                let chunk = chunk.path();
+                let Some(stem) = chunk
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| n.strip_suffix(".chunk"))
+                else {
+                    continue;
+                };
+                if stem.parse::<u64>().ok().is_none_or(|i| i >= len) {
+                    garbage.push(chunk);
+                }
+            }
+        }
+    }
+
+    for path in garbage {
+        report.bytes_freed += fs::metadata(&path)?.len();
+        if !dry_run {
+            fs::remove_file(&path)?;
+            if let Some(parent) = path.parent() {
+                // Only succeeds once the directory is empty.
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        report.removed.push(path);
+    }
+    Ok(report)
+}
@@ -1,8 +1,17 @@
+pub mod bitfield;
 pub mod chunk;
 pub mod crypto;
+pub mod delta;
 pub mod error;
+pub mod fetch;
+pub mod gc;
 pub mod manifest;
+pub mod merkle;
 pub mod net;
+pub mod outboard;
+pub mod peers;
+pub mod resume;
 pub mod scanner;
 pub mod storage;
 pub mod sync;
+pub mod uri;
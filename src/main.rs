@@ -1,15 +1,25 @@
 use clap::{Parser, Subcommand};
 use dialoguer::Confirm;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use p2rent::chunk::{self, Chunk};
-use p2rent::crypto::load_or_create_keypair;
-use p2rent::manifest::{self, Manifest};
-use p2rent::net::protocol::Message;
-use p2rent::net::quic::{self, Peer, QuicClient, QuicServer};
+use p2rent::chunk::Chunking;
+use p2rent::crypto::{self, NodeKeypair, load_or_create_keypair};
+use p2rent::fetch::{self, FetchTarget, FileFetch};
+use p2rent::gc;
+use p2rent::manifest::{self, AnyManifest, DirectoryManifest, Manifest, PublisherPolicy};
+use p2rent::merkle;
+use p2rent::net::download;
+use p2rent::net::push;
+use p2rent::net::quic::{Peer, PeerAddr, QuicClient, QuicServer};
+use p2rent::net::server::{ServeConfig, handle_peer, serve_forever};
+use p2rent::peers::{self, AuthorizedPeers, KnownPeers};
 use p2rent::scanner;
 use p2rent::storage;
+use p2rent::sync::{self, ShareOptions};
+use p2rent::uri::{self, ShareUri};
+use std::collections::BTreeSet;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
 
 #[derive(Parser, Debug)]
@@ -30,27 +40,138 @@ enum Commands {
         addr: String,
         #[arg(long, default_value = "chunks")]
         storage_dir: PathBuf,
+        #[arg(long, default_value = "manifests")]
+        manifest_dir: PathBuf,
+        /// Node ids allowed to `push` content here; repeat or
+        /// comma-separate for several.
+        #[arg(long, value_delimiter = ',')]
+        accept_push_from: Vec<String>,
+        /// File of node ids or base64 public keys, one per line with an
+        /// optional label, allowed to connect; edits apply without a
+        /// restart. Anyone may connect without it.
+        #[arg(long)]
+        authorized_peers: Option<PathBuf>,
     },
     Share {
         path: PathBuf,
+        /// Chunk size in bytes; the average size with --cdc.
         #[arg(long, default_value_t = 1024 * 1024)]
         chunk_size: usize,
+        /// Cut chunks at content-defined boundaries (FastCDC) instead of
+        /// fixed offsets, so edits only change the chunks around them.
+        #[arg(long, default_value_t = false)]
+        cdc: bool,
+        /// Smallest chunk with --cdc (default: a quarter of --chunk-size).
+        #[arg(long, requires = "cdc")]
+        min_chunk_size: Option<u32>,
+        /// Largest chunk with --cdc (default: four times --chunk-size).
+        #[arg(long, requires = "cdc")]
+        max_chunk_size: Option<u32>,
+        /// Address this file will be served from, added to the printed
+        /// `p2rent://` link; repeat or comma-separate for several.
+        #[arg(long = "peer", value_delimiter = ',')]
+        peers: Vec<String>,
         #[arg(long, default_value = "manifests")]
         manifest_dir: PathBuf,
         #[arg(long, default_value = "chunks")]
         storage_dir: PathBuf,
         #[arg(long, default_value_t = false)]
         parallel: bool,
+        /// Overwrite a manifest already recorded under the same share id
+        /// for different content.
+        #[arg(long, default_value_t = false)]
+        replace: bool,
     },
     Fetch {
+        /// A `p2rent://` link; the manifest is fetched from its peer hints
+        /// and any --addr peers and checked against its content ID.
+        #[arg(conflicts_with_all = ["manifest", "root"])]
+        uri: Option<String>,
+        /// Peer to download from, as `host:port` or `<node id>@host:port`
+        /// to refuse any other node; repeat or comma-separate for several.
+        #[arg(long = "addr", required_unless_present = "uri", value_delimiter = ',')]
+        addrs: Vec<String>,
+        /// File or directory manifest to fetch.
+        #[arg(
+            long,
+            required_unless_present_any = ["root", "uri", "share"],
+            conflicts_with = "root"
+        )]
+        manifest: Option<PathBuf>,
+        /// Content ID printed by `share`; the manifest (or at least the
+        /// chunk list) is fetched from the peers and verified against it, so
+        /// no manifest file is needed.
         #[arg(long)]
-        addr: String,
-        #[arg(long)]
-        manifest: PathBuf,
+        root: Option<String>,
         #[arg(long)]
         out: Option<PathBuf>,
+        /// Share id to request. Without --manifest, --root or a link, the
+        /// manifest of the file or directory shared under it is fetched
+        /// from the peers.
+        #[arg(long, alias = "stem")]
+        share: Option<String>,
+        /// Chunk requests kept in flight per peer.
+        #[arg(long, default_value_t = download::DEFAULT_WINDOW)]
+        window: usize,
+        /// Store verified chunks and the manifest so this node can serve them.
+        #[arg(long, default_value_t = false)]
+        seed: bool,
+        /// Serve while downloading and keep serving afterwards (implies --seed).
+        #[arg(long)]
+        serve: Option<String>,
+        /// Older local copy whose unchanged chunks are reused instead of
+        /// downloaded (a directory for directory manifests). Defaults to an
+        /// existing --out.
+        #[arg(long)]
+        seed_from: Option<PathBuf>,
+        /// Only accept manifests signed by this publisher node id; repeat or
+        /// comma-separate for several.
+        #[arg(long = "trust", value_delimiter = ',')]
+        trusted: Vec<String>,
+        /// Refuse manifests without a valid publisher signature.
+        #[arg(long, default_value_t = false)]
+        require_signed: bool,
+        #[arg(long, default_value = "manifests")]
+        manifest_dir: PathBuf,
+        #[arg(long, default_value = "chunks")]
+        storage_dir: PathBuf,
+    },
+    /// Send a shared file or directory to a peer that accepts pushes from
+    /// this node; it pulls whatever chunks it lacks.
+    Push {
+        /// Share id of the file or directory, as printed by `share`.
+        share: String,
+        #[arg(long)]
+        addr: String,
+        #[arg(long, default_value = "manifests")]
+        manifest_dir: PathBuf,
+        #[arg(long, default_value = "chunks")]
+        storage_dir: PathBuf,
+    },
+    /// List the files and directories a peer is serving.
+    Ls {
         #[arg(long)]
-        stem: Option<String>,
+        addr: String,
+    },
+    /// Trust the node now answering at an address in place of the one
+    /// recorded for it in `known_peers`.
+    AcceptPeer {
+        /// `host:port`, or `<node id>@host:port` to accept only that node.
+        addr: String,
+    },
+    /// Delete stored chunks that no manifest refers to any more.
+    Gc {
+        #[arg(long, default_value = "chunks")]
+        storage_dir: PathBuf,
+        #[arg(long, default_value = "manifests")]
+        manifest_dir: PathBuf,
+        /// Only report what would be deleted.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        /// Collect even when --manifest-dir holds no manifests, deleting
+        /// every stored chunk.
+        #[arg(long, default_value_t = false)]
+        allow_empty: bool,
     },
 }
 
@@ -59,34 +180,67 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Serve { addr, storage_dir } => {
+        Commands::Serve {
+            addr,
+            storage_dir,
+            manifest_dir,
+            accept_push_from,
+            authorized_peers,
+        } => {
+            for id in &accept_push_from {
+                check_node_id("--accept-push-from", id)?;
+            }
+            let _lock = storage::StoreLock::shared(&storage_dir)?;
             let keypair = load_or_create_keypair()?;
             let listen_addr: SocketAddr = addr.parse()?;
+            let node = crypto::node_id(&keypair);
             let server = QuicServer::bind(listen_addr, keypair).await?;
-            println!("Listening on {listen_addr}");
-
-            loop {
-                match server.accept_and_handshake().await {
-                    Ok(peer) => {
-                        println!("Accepted new peer: {}", peer.id);
-                        let storage_dir = storage_dir.clone();
-                        tokio::spawn(async move {
-                            handle_peer(peer, storage_dir).await;
-                        });
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to accept peer: {}", e);
-                    }
-                }
+            println!("Listening on {listen_addr} as node {node}");
+            let mut config = ServeConfig::new(storage_dir, manifest_dir).accept_push_from(
+                accept_push_from
+                    .iter()
+                    .map(|id| id.to_lowercase())
+                    .collect(),
+            );
+            if let Some(path) = &authorized_peers {
+                let peers = AuthorizedPeers::load(path)?;
+                println!(
+                    "Only the {} peer(s) in {} may connect",
+                    peers.len(),
+                    path.display()
+                );
+                config = config.authorized_peers(peers);
             }
+            serve_forever(server, Arc::new(config)).await;
         }
         Commands::Share {
             path,
             chunk_size,
+            cdc,
+            min_chunk_size,
+            max_chunk_size,
+            peers,
             manifest_dir,
             storage_dir,
             parallel,
+            replace,
         } => {
+            let chunking = if cdc {
+                let avg = u32::try_from(chunk_size)?;
+                Chunking::cdc(avg, min_chunk_size, max_chunk_size)?
+            } else {
+                anyhow::ensure!(chunk_size > 0, "--chunk-size must be positive");
+                Chunking::Fixed
+            };
+            let _lock = storage::StoreLock::shared(&storage_dir)?;
+            let keypair = load_or_create_keypair()?;
+            let settings = ShareSettings {
+                options: ShareOptions::new(chunk_size, &storage_dir, &manifest_dir)
+                    .with_chunking(chunking)
+                    .signed_by(&keypair)
+                    .replace(replace),
+                peers: &peers,
+            };
             if path.is_dir() {
                 let files = scanner::scan_directory(&path)?;
                 if files.is_empty() {
@@ -124,28 +278,26 @@ async fn main() -> anyhow::Result<()> {
 
                 let total_bytes = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
                 let total_chunks = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+                let shared = std::sync::Mutex::new(Vec::with_capacity(files.len()));
 
                 if parallel {
                     use rayon::prelude::*;
                     files.par_iter().for_each(|file| {
-                        if let Err(e) =
-                            share_one_file(file, chunk_size, &manifest_dir, &storage_dir, None)
-                        {
-                            eprintln!("Failed to share {:?}: {}", file, e);
-                        } else if let Ok(meta) = std::fs::metadata(file) {
-                            total_bytes.fetch_add(meta.len(), std::sync::atomic::Ordering::Relaxed);
+                        match share_one_file(file, &path, &settings, None) {
+                            Ok(info) => {
+                                total_bytes.fetch_add(
+                                    info.file_size,
+                                    std::sync::atomic::Ordering::Relaxed,
+                                );
+                                shared.lock().unwrap().push((file.clone(), info.manifest));
+                            }
+                            Err(e) => eprintln!("Failed to share {:?}: {}", file, e),
                         }
                         total_pb.inc(1);
                     });
                 } else {
                     for file in &files {
-                        match share_one_file(
-                            file,
-                            chunk_size,
-                            &manifest_dir,
-                            &storage_dir,
-                            Some(&m),
-                        ) {
+                        match share_one_file(file, &path, &settings, Some(&m)) {
                             Ok(info) => {
                                 total_bytes.fetch_add(
                                     info.file_size,
@@ -155,6 +307,7 @@ async fn main() -> anyhow::Result<()> {
                                     info.num_chunks as u64,
                                     std::sync::atomic::Ordering::Relaxed,
                                 );
+                                shared.lock().unwrap().push((file.clone(), info.manifest));
                             }
                             Err(e) => eprintln!("Failed to share {:?}: {}", file, e),
                         }
@@ -166,17 +319,35 @@ async fn main() -> anyhow::Result<()> {
                 let elapsed = started.elapsed();
                 let tb = total_bytes.load(std::sync::atomic::Ordering::Relaxed);
                 let tc = total_chunks.load(std::sync::atomic::Ordering::Relaxed);
+
+                // The directory manifest lets a peer fetch the whole tree in
+                // one go; it is only written when every file made it in.
+                let shared = shared.into_inner().unwrap();
+                let dir_manifest_path = if shared.len() == files.len() {
+                    let dir_manifest = DirectoryManifest::new(&path, shared)?;
+                    let out = manifest::directory_manifest_path(&manifest_dir, &dir_manifest.name);
+                    if !replace {
+                        manifest::check_replace_directory(&out, &dir_manifest)?;
+                    }
+                    manifest::write_directory_manifest(&dir_manifest, &out)?;
+                    format!("{:?}", out)
+                } else {
+                    format!(
+                        "not written ({} file(s) failed)",
+                        files.len() - shared.len()
+                    )
+                };
                 println!(
-                    "\nShared directory report:\n- Files: {}\n- Total bytes: {}\n- Total chunks: {}\n- Elapsed: {:.2?}\n- Throughput: {:.2} MB/s",
+                    "\nShared directory report:\n- Files: {}\n- Total bytes: {}\n- Total chunks: {}\n- Directory manifest: {}\n- Elapsed: {:.2?}\n- Throughput: {:.2} MB/s",
                     files.len(),
                     tb,
                     tc,
+                    dir_manifest_path,
                     elapsed,
                     (tb as f64 / 1_048_576.0) / elapsed.as_secs_f64()
                 );
             } else if path.is_file() {
-                if let Err(e) = share_one_file(&path, chunk_size, &manifest_dir, &storage_dir, None)
-                {
+                if let Err(e) = share_one_file(&path, &path, &settings, None) {
                     eprintln!("Failed: {}", e);
                 }
             } else {
@@ -184,209 +355,521 @@ async fn main() -> anyhow::Result<()> {
             }
         }
         Commands::Fetch {
-            addr,
+            uri,
+            mut addrs,
             manifest,
+            root,
             out,
-            stem,
+            mut share,
+            window,
+            seed,
+            serve,
+            seed_from,
+            trusted,
+            require_signed,
+            manifest_dir,
+            storage_dir,
         } => {
-            // Load manifest and prepare output
-            let manifest_data = p2rent::manifest::read_manifest(&manifest)?;
-            let out_path = out.unwrap_or_else(|| PathBuf::from(&manifest_data.file_name));
-            let stem = stem.unwrap_or_else(|| {
-                PathBuf::from(&manifest_data.file_name)
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("file")
-                    .to_string()
-            });
-
+            for id in &trusted {
+                check_node_id("--trust", id)?;
+            }
+            let uri = uri.as_deref().map(ShareUri::parse).transpose()?;
+            let mut policy = PublisherPolicy {
+                require_signed,
+                trusted: trusted.iter().map(|id| id.to_lowercase()).collect(),
+            };
+            if let Some(u) = &uri {
+                addrs.extend(u.peers.iter().cloned());
+                policy.trusted.extend(u.publisher.clone());
+            }
+            anyhow::ensure!(
+                !addrs.is_empty(),
+                "no peers to fetch from: pass --addr or a link with peer hints"
+            );
+            let addrs = addrs
+                .iter()
+                .map(|a| PeerAddr::parse(a))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(path) = &seed_from {
+                anyhow::ensure!(
+                    path.exists(),
+                    "--seed-from {} does not exist",
+                    path.display()
+                );
+            }
             let keypair = load_or_create_keypair()?;
+            let seed = seed || serve.is_some();
+            let _lock = if seed {
+                Some(storage::StoreLock::shared(&storage_dir)?)
+            } else {
+                None
+            };
+
+            let server_task = match &serve {
+                Some(addr) => {
+                    let listen_addr: SocketAddr = addr.parse()?;
+                    let server = QuicServer::bind(listen_addr, keypair.clone()).await?;
+                    println!(
+                        "Seeding on {listen_addr} as node {}",
+                        crypto::node_id(&keypair)
+                    );
+                    let config = ServeConfig::new(storage_dir.clone(), manifest_dir.clone());
+                    Some(tokio::spawn(serve_forever(server, Arc::new(config))))
+                }
+                None => None,
+            };
+
             let client = QuicClient::new().await?;
-            let addr: SocketAddr = addr.parse()?;
-            let peer = client.connect_and_handshake(addr, &keypair).await?;
-            let total = manifest_data.chunks.len() as u64;
-
-            let pb = ProgressBar::new(total);
-            pb.set_style(
-                ProgressStyle::with_template(
-                    "{spinner} [{bar:40}] {pos}/{len} chunks {elapsed_precise}",
-                )
-                .unwrap()
-                .progress_chars("=>-"),
-            );
+            let mut known = KnownPeers::load(&KnownPeers::default_path())?;
+            let mut peers = Vec::with_capacity(addrs.len());
+            for addr in &addrs {
+                match connect_known(&client, addr, &keypair, &mut known).await {
+                    Ok(peer) => {
+                        println!("Connected to {} ({addr})", peer.id);
+                        peers.push(peer);
+                    }
+                    Err(e) => eprintln!("Failed to connect to {addr}: {e}"),
+                }
+            }
+            anyhow::ensure!(!peers.is_empty(), "could not connect to any peer");
 
-            let mut received: Vec<Option<Vec<u8>>> = vec![None; total as usize];
+            let mut targets = Vec::new();
+            let mut tree = None;
+            let mut by_root = false;
+            let target = match (&manifest, root, &uri) {
+                (Some(path), _, _) => manifest::read_any_manifest(path)?,
+                (None, None, None) => {
+                    let name = share
+                        .take()
+                        .expect("clap requires --manifest, --root, --share or a link");
+                    let m = download::fetch_shared_manifest(&peers, &name).await?;
+                    println!("Fetched manifest of share {name}");
+                    m
+                }
+                (None, root, _) => {
+                    let id = match &root {
+                        Some(hex) => merkle::parse_content_id(hex)
+                            .ok_or_else(|| anyhow::anyhow!("--root must be 64 hex digits"))?,
+                        None => uri.as_ref().expect("matched above without a link").root,
+                    };
+                    match download::fetch_manifest(&peers, &id).await {
+                        Ok(m) => {
+                            println!(
+                                "Fetched manifest of {} ({} bytes)",
+                                m.file_name, m.file_size
+                            );
+                            AnyManifest::File(m)
+                        }
+                        Err(_) => {
+                            // Size, chunking and chunk list all come from
+                            // peers that only serve the chunk list.
+                            by_root = true;
+                            let hex = merkle::content_id_hex(&id);
+                            let mut m = Manifest::new(hex.clone(), 0);
+                            m.root = Some(hex);
+                            AnyManifest::File(m)
+                        }
+                    }
+                }
+            };
+            match target {
+                AnyManifest::File(m) => {
+                    let out_path = match out {
+                        Some(out) => out,
+                        None => {
+                            let name = uri
+                                .as_ref()
+                                .and_then(|u| u.name.clone())
+                                .unwrap_or_else(|| m.file_name.clone());
+                            anyhow::ensure!(
+                                uri::is_plain_file_name(&name),
+                                "manifest names the file {name:?}; choose a path with --out"
+                            );
+                            PathBuf::from(name)
+                        }
+                    };
+                    targets.push(FetchTarget {
+                        share: share.unwrap_or_else(|| m.share_id()),
+                        seed_from: seed_from.unwrap_or_else(|| out_path.clone()),
+                        out_path,
+                        manifest: m,
+                    })
+                }
+                AnyManifest::Directory(d) => {
+                    anyhow::ensure!(
+                        share.is_none(),
+                        "--share only applies to single-file manifests"
+                    );
+                    d.validate()?;
+                    d.check_paths()?;
+                    let root = match out {
+                        Some(out) => out,
+                        None => {
+                            let name = d.name.rsplit('/').next().unwrap_or(&d.name);
+                            anyhow::ensure!(
+                                uri::is_plain_file_name(name),
+                                "manifest names the directory {:?}; choose a path with --out",
+                                d.name
+                            );
+                            PathBuf::from(name)
+                        }
+                    };
+                    for entry in &d.files {
+                        let out_path = root.join(&entry.path);
+                        targets.push(FetchTarget {
+                            share: entry.manifest.share_id(),
+                            seed_from: match &seed_from {
+                                Some(dir) => dir.join(&entry.path),
+                                None => out_path.clone(),
+                            },
+                            out_path,
+                            manifest: entry.manifest.clone(),
+                        });
+                    }
+                    tree = Some((root, d));
+                }
+            }
 
-            for index in 0..total {
-                let (mut send, mut recv) = peer.connection.open_bi().await?;
-                let req = Message::RequestChunk {
-                    stem: stem.clone(),
-                    index,
-                };
-                quic::send_message(&mut send, &req).await?;
-                if let Message::Chunk { index: idx, data } =
-                    quic::receive_message(&mut recv).await?
-                    && idx == index
-                {
-                    received[idx as usize] = Some(data);
-                    pb.inc(1);
+            let mut publishers = BTreeSet::new();
+            for t in &targets {
+                if let Some(id) = policy.check(&t.manifest)? {
+                    publishers.insert(id);
                 }
             }
-            pb.finish_with_message("downloaded");
+            for id in &publishers {
+                println!("Signed by publisher {id}");
+            }
 
-            let mut chunks_vec: Vec<Chunk> = Vec::with_capacity(received.len());
-            for (i, maybe) in received.into_iter().enumerate() {
-                let data = maybe.ok_or_else(|| anyhow::anyhow!("missing chunk {i} from server"))?;
-                let hash: [u8; 32] = blake3::hash(&data).into();
-                let expected = manifest_data.chunks[i];
+            for t in &mut targets {
+                if !(by_root || t.manifest.is_compact()) {
+                    t.manifest.verify_root()?;
+                    continue;
+                }
+                let hex = t.manifest.root.clone().unwrap_or_default();
+                let id = merkle::parse_content_id(&hex)
+                    .ok_or_else(|| anyhow::anyhow!("invalid content ID {hex:?}"))?;
+                let list = download::fetch_chunk_list(&peers, &id).await?;
+                if by_root {
+                    t.manifest.file_size = list.file_size;
+                    t.manifest.chunk_size = list.chunk_size;
+                    t.manifest.chunking = list.chunking;
+                }
+                anyhow::ensure!(
+                    list.file_size == t.manifest.file_size,
+                    "peers describe {hex} as {} bytes, the manifest says {}",
+                    list.file_size,
+                    t.manifest.file_size
+                );
+                t.manifest.fill_chunks(&list.leaves)?;
+                println!(
+                    "Verified {} chunk hashes for {}",
+                    list.leaves.len(),
+                    t.share
+                );
+            }
+
+            if seed {
+                fetch::seed_manifests(&manifest_dir, &targets, tree.as_ref().map(|(_, d)| d))?;
+            }
+
+            let seed_dir = seed.then_some(storage_dir.as_path());
+            for target in &targets {
+                fetch_file(&peers, target, window, seed_dir).await?;
+            }
+
+            if let Some((root, d)) = &tree {
+                for dir in &d.empty_dirs {
+                    std::fs::create_dir_all(root.join(dir))?;
+                }
+                for entry in &d.files {
+                    entry.apply_metadata(&root.join(&entry.path))?;
+                }
+                println!(
+                    "Rebuilt {} ({} files, {} bytes)",
+                    root.display(),
+                    d.files.len(),
+                    d.total_size()
+                );
+            }
+
+            if let Some(task) = server_task {
+                println!("Download complete; still seeding (Ctrl-C to stop)");
+                task.await?;
+            }
+        }
+        Commands::Push {
+            share,
+            addr,
+            manifest_dir,
+            storage_dir,
+        } => {
+            let file_path = manifest::manifest_path(&manifest_dir, &share);
+            let offered = if file_path.is_file() {
+                AnyManifest::File(manifest::read_manifest(&file_path)?)
+            } else {
+                let dir_path = manifest::directory_manifest_path(&manifest_dir, &share);
                 anyhow::ensure!(
-                    hash == expected,
-                    "chunk {i} integrity check failed — hash mismatch"
+                    dir_path.is_file(),
+                    "nothing is shared as {share:?} in {}",
+                    manifest_dir.display()
+                );
+                manifest::read_any_manifest(&dir_path)?
+            };
+            let _lock = storage::StoreLock::shared(&storage_dir)?;
+            let keypair = load_or_create_keypair()?;
+            let client = QuicClient::new().await?;
+            let mut known = KnownPeers::load(&KnownPeers::default_path())?;
+            let peer =
+                connect_known(&client, &PeerAddr::parse(&addr)?, &keypair, &mut known).await?;
+            println!(
+                "Pushing {share} to {} as {}",
+                peer.id,
+                crypto::node_id(&keypair)
+            );
+            // The receiver pulls chunks back over this connection.
+            let config = ServeConfig::new(storage_dir, manifest_dir);
+            tokio::spawn(handle_peer(peer.clone(), Arc::new(config)));
+            let report = push::push(&peer, &offered).await?;
+            println!(
+                "Pushed {share}: {} chunk(s) sent, {} already there",
+                report.fetched, report.present
+            );
+        }
+        Commands::Ls { addr } => {
+            let keypair = load_or_create_keypair()?;
+            let client = QuicClient::new().await?;
+            let mut known = KnownPeers::load(&KnownPeers::default_path())?;
+            let peer =
+                connect_known(&client, &PeerAddr::parse(&addr)?, &keypair, &mut known).await?;
+            let shares = download::list_shares(&peer).await?;
+            let width = shares.iter().map(|s| s.share.len() + 1).fold(5, usize::max);
+            println!(
+                "{:width$}  {:>12}  {:>7}  {:64}  PUBLISHER",
+                "SHARE", "SIZE", "CHUNKS", "CONTENT ID"
+            );
+            for s in &shares {
+                let name = if s.directory {
+                    format!("{}/", s.share)
+                } else {
+                    s.share.clone()
+                };
+                println!(
+                    "{name:width$}  {:>12}  {:>7}  {:64}  {}",
+                    s.size,
+                    s.chunks,
+                    s.root.as_ref().map_or("-".into(), merkle::content_id_hex),
+                    s.publisher.as_deref().unwrap_or("-")
                 );
-                chunks_vec.push(Chunk {
-                    index: i as u64,
-                    hash,
-                    size: data.len(),
-                    data,
-                });
             }
-            chunk::combine_chunks(&chunks_vec, &out_path)?;
-            println!("Written {}", out_path.display());
+            println!("\n{} share(s) on {} ({addr})", shares.len(), peer.id);
+        }
+        Commands::AcceptPeer { addr } => {
+            let addr = PeerAddr::parse(&addr)?;
+            let keypair = load_or_create_keypair()?;
+            let client = QuicClient::new().await?;
+            let peer = client.connect_peer(&addr, &keypair).await?;
+            let mut known = KnownPeers::load(&KnownPeers::default_path())?;
+            match known.insert(&addr.addr, &peer.id) {
+                Some(previous) if previous != peer.id => {
+                    println!("{}: node {previous} replaced by {}", addr.addr, peer.id)
+                }
+                Some(_) => println!("{}: node {} was already known", addr.addr, peer.id),
+                None => println!("{}: recorded node {}", addr.addr, peer.id),
+            }
+            known.save()?;
         }
+        Commands::Gc {
+            storage_dir,
+            manifest_dir,
+            dry_run,
+            allow_empty,
+        } => {
+            let options = gc::GcOptions {
+                dry_run,
+                allow_empty,
+            };
+            let report = gc::collect_garbage(&storage_dir, &manifest_dir, options)?;
+            for path in &report.removed {
+                println!(
+                    "{} {}",
+                    if dry_run { "would remove" } else { "removed" },
+                    path.display()
+                );
+            }
+            println!(
+                "\nGC report:\n- Manifests: {}\n- Referenced chunks: {}\n- {}: {} files, {} bytes",
+                report.manifests,
+                report.referenced,
+                if dry_run { "Reclaimable" } else { "Freed" },
+                report.removed.len(),
+                report.bytes_freed
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// [`peers::connect_known`], saying so when a new node is recorded.
+async fn connect_known(
+    client: &QuicClient,
+    addr: &PeerAddr,
+    keypair: &NodeKeypair,
+    known: &mut KnownPeers,
+) -> anyhow::Result<Peer> {
+    let (peer, recorded) = peers::connect_known(client, addr, keypair, known).await?;
+    if recorded {
+        println!(
+            "Recorded node {} for {} in {}",
+            peer.id,
+            addr.addr,
+            known.path().display()
+        );
+    }
+    Ok(peer)
+}
+
+fn check_node_id(flag: &str, id: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()),
+        "{flag} takes a 64-hex-digit node id, got {id:?}"
+    );
+    Ok(())
+}
+
+/// Downloads one file into `target.out_path` with a progress bar, resuming
+/// an interrupted attempt; Ctrl-C stops it with the resume record saved.
+async fn fetch_file(
+    peers: &[Peer],
+    target: &FetchTarget,
+    window: usize,
+    seed_dir: Option<&Path>,
+) -> anyhow::Result<()> {
+    let Some(fetch) = FileFetch::open(target, seed_dir)? else {
+        println!("Up to date: {}", target.out_path.display());
+        return Ok(());
+    };
+    let total = fetch.total();
+    if fetch.reused > 0 {
+        println!(
+            "Reused {}/{total} chunks from {}",
+            fetch.reused,
+            target.seed_from.display()
+        );
+    }
+    let already = fetch.on_disk();
+    if already > 0 {
+        println!("Resuming: {already}/{total} chunks already on disk");
     }
 
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{spinner} [{bar:40}] {pos}/{len} chunks {elapsed_precise} {msg}",
+        )
+        .unwrap()
+        .progress_chars("=>-"),
+    );
+    pb.set_message(target.share.clone());
+    pb.set_position(already);
+
+    let interrupt = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    if let Err(e) = fetch.download(peers, window, interrupt, || pb.inc(1)).await {
+        pb.abandon();
+        return Err(e.into());
+    }
+    pb.finish_with_message("downloaded");
+    println!("Written {}", target.out_path.display());
     Ok(())
 }
 
 struct ShareInfo {
     file_size: u64,
     num_chunks: usize,
+    manifest: Manifest,
+}
+
+/// How `share` chunks, stores and signs each file, and the peer hints for
+/// the printed link.
+struct ShareSettings<'a> {
+    options: ShareOptions<'a>,
+    peers: &'a [String],
 }
 
+/// Shares `file`, found while sharing `root`, under its relative path.
 fn share_one_file(
     file: &Path,
-    chunk_size: usize,
-    manifest_dir: &Path,
-    storage_dir: &Path,
+    root: &Path,
+    settings: &ShareSettings,
     mp: Option<&MultiProgress>,
 ) -> p2rent::error::Result<ShareInfo> {
+    let ShareSettings { options, peers } = settings;
+    let ShareOptions {
+        chunk_size,
+        chunking,
+        storage_dir,
+        ..
+    } = *options;
     let file_name = file
         .file_name()
         .and_then(|s| s.to_str())
         .unwrap_or("unknown")
         .to_string();
-    let stem = file
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or(&file_name);
 
     let meta = std::fs::metadata(file)?;
     let approx_total_chunks = (meta.len() as usize).div_ceil(chunk_size) as u64;
 
-    let pb_spinner = ProgressBar::new_spinner();
-    pb_spinner.set_style(ProgressStyle::with_template("{spinner} chunking {msg}").unwrap());
-    pb_spinner.set_message(file_name.clone());
-    pb_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-
     let started = Instant::now();
-    let chunks: Vec<Chunk> = chunk::split_file(file, chunk_size)?;
-    pb_spinner.finish_and_clear();
-
-    let file_out_dir = {
-        let mut d = PathBuf::from(storage_dir);
-        d.push(stem);
-        d
-    };
-    std::fs::create_dir_all(&file_out_dir)?;
-
-    let save_pb = if let Some(m) = mp {
-        let bar = m.add(ProgressBar::new(chunks.len() as u64));
-        bar.set_style(
-            ProgressStyle::with_template("{spinner} [{bar:40}] {pos}/{len} chunks {msg}")
-                .unwrap()
-                .progress_chars("=>-"),
-        );
-        bar.set_message(file_name.clone());
-        Some(bar)
-    } else {
-        let bar = ProgressBar::new(chunks.len() as u64);
-        bar.set_style(
-            ProgressStyle::with_template("{spinner} [{bar:40}] {pos}/{len} chunks {msg}")
-                .unwrap()
-                .progress_chars("=>-"),
-        );
-        bar.set_message(file_name.clone());
-        Some(bar)
+    let bar = match mp {
+        Some(m) => m.add(ProgressBar::new(approx_total_chunks)),
+        None => ProgressBar::new(approx_total_chunks),
     };
+    bar.set_style(
+        ProgressStyle::with_template("{spinner} [{bar:40}] {pos}/{len} chunks {msg}")
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+    bar.set_message(file_name);
 
-    let dir_str = file_out_dir
-        .to_str()
-        .ok_or_else(|| p2rent::error::SyncError::Other("non-UTF-8 path".into()))?;
-    for c in &chunks {
-        storage::save_chunk(dir_str, c)?;
-        if let Some(pb) = &save_pb {
-            pb.inc(1);
+    let mut new_chunks = 0;
+    let manifest = sync::share_file(file, root, options, |_, new| {
+        if new {
+            new_chunks += 1;
         }
-    }
-    if let Some(pb) = &save_pb {
-        pb.finish_with_message("saved");
-    }
-
-    let manifest = Manifest::from_chunks(file_name.clone(), chunk_size, &chunks);
-    let mut out_path = PathBuf::from(manifest_dir);
-    std::fs::create_dir_all(&out_path)?;
-    out_path.push(format!("{}.manifest.json", stem));
-    manifest::write_manifest(&manifest, &out_path)?;
+        bar.inc(1);
+    })?;
+    bar.finish_with_message("saved");
+    let out_path = manifest::manifest_path(options.manifest_dir, &manifest.share);
 
     let elapsed = started.elapsed();
     println!(
-        "\nShared file report:\n- File: {}\n- Size: {} bytes\n- Chunk size: {} bytes\n- Chunks: {} (approx announced: {})\n- Manifest: {:?}\n- Chunks dir: {:?}\n- Elapsed: {:.2?}\n- Throughput: {:.2} MB/s",
+        "\nShared file report:\n- File: {}\n- Share: {}\n- Size: {} bytes\n- Chunk size: {}\n- Chunks: {} (approx announced: {}, new in store: {})\n- Content ID: {}\n- Publisher: {}\n- Link: {}\n- Manifest: {:?}\n- Chunk store: {:?}\n- Elapsed: {:.2?}\n- Throughput: {:.2} MB/s",
         file.display(),
+        manifest.share,
         meta.len(),
-        chunk_size,
-        chunks.len(),
+        match chunking {
+            Chunking::Fixed => format!("{chunk_size} bytes"),
+            Chunking::Cdc { min, avg, max } => {
+                format!("{min}..{max} bytes, avg {avg} (content-defined)")
+            }
+        },
+        manifest.chunks.len(),
         approx_total_chunks,
+        new_chunks,
+        manifest.root.as_deref().unwrap_or_default(),
+        options.keypair.map(crypto::node_id).unwrap_or_default(),
+        ShareUri::for_manifest(&manifest, peers.to_vec())?,
         out_path,
-        file_out_dir,
+        storage_dir,
         elapsed,
         (meta.len() as f64 / 1_048_576.0) / elapsed.as_secs_f64()
     );
 
     Ok(ShareInfo {
         file_size: meta.len(),
-        num_chunks: chunks.len(),
+        num_chunks: manifest.chunks.len(),
+        manifest,
     })
 }
-
-async fn handle_peer(peer: Peer, storage_dir: PathBuf) {
-    println!("Handling connection with {}", peer.id);
-    while let Ok((mut send, mut recv)) = peer.connection.accept_bi().await {
-        match quic::receive_message(&mut recv).await {
-            Ok(Message::RequestChunk { stem, index }) => {
-                let mut dir = storage_dir.clone();
-                dir.push(stem);
-                let dir_str = dir.to_str().unwrap_or(".");
-                match storage::load_chunk(dir_str, index) {
-                    Ok(ch) => {
-                        let msg = Message::Chunk {
-                            index: ch.index,
-                            data: ch.data,
-                        };
-                        let _ = quic::send_message(&mut send, &msg).await;
-                    }
-                    Err(e) => {
-                        let _ = quic::send_message(&mut send, &Message::Bye).await;
-                        eprintln!("load_chunk error: {}", e);
-                    }
-                }
-            }
-            Ok(_) => {
-                let _ = quic::send_message(&mut send, &Message::Bye).await;
-            }
-            Err(e) => {
-                eprintln!("recv error: {}", e);
-                break;
-            }
-        }
-    }
-}
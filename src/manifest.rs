@@ -1,33 +1,524 @@
-use crate::error::Result;
+use crate::chunk::{Chunk, ChunkReader, ChunkWriter, Chunking};
+use crate::crypto::{self, NodeId, NodeKeypair};
+use crate::error::{Result, SyncError};
+use crate::merkle::{self, MerkleTree};
+use crate::scanner;
+use base64::{Engine as _, engine::general_purpose};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-#[derive(Serialize, Deserialize, Debug)]
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Manifest {
+    /// Id the file is shared under; see [`share_id_for`]. Empty in manifests
+    /// written before share ids existed.
+    #[serde(default)]
+    pub share: String,
     pub file_name: String,
     pub file_size: u64,
+    /// Size of every chunk but the last with fixed chunking; the average
+    /// size with content-defined chunking.
     pub chunk_size: usize,
+    #[serde(default, skip_serializing_if = "Chunking::is_fixed")]
+    pub chunking: Chunking,
+    /// Content ID (hex) from [`merkle::content_id`]. A manifest that has it
+    /// may leave `chunks` out; the list is then fetched from peers and
+    /// checked against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    #[serde(default)]
     pub chunks: Vec<[u8; 32]>,
+    /// Length of each chunk; only recorded for content-defined chunking,
+    /// where it cannot be derived from `chunk_size`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunk_sizes: Vec<u32>,
+    /// Ed25519 public key (base64) of the node that signed the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    /// The publisher's signature (base64) over [`Manifest::signing_payload`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 impl Manifest {
-    pub fn from_chunks(
-        file_name: String,
-        chunk_size: usize,
-        chunks: &[super::chunk::Chunk],
-    ) -> Self {
-        let file_size = chunks.iter().map(|c| c.data.len() as u64).sum();
-        let hashes: Vec<[u8; 32]> = chunks.iter().map(|c| c.hash).collect();
+    pub fn new(file_name: String, chunk_size: usize) -> Self {
         Manifest {
+            share: file_name.clone(),
             file_name,
-            file_size,
+            file_size: 0,
             chunk_size,
-            chunks: hashes,
+            chunking: Chunking::Fixed,
+            root: None,
+            chunks: Vec::new(),
+            chunk_sizes: Vec::new(),
+            publisher: None,
+            signature: None,
+        }
+    }
+
+    /// Records chunks cut according to `chunking` instead of fixed-size ones.
+    pub fn with_chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Appends the next chunk, for building a manifest while streaming.
+    pub fn push_chunk(&mut self, chunk: &Chunk) {
+        self.file_size += chunk.size as u64;
+        self.chunks.push(chunk.hash);
+        if !self.chunking.is_fixed() {
+            self.chunk_sizes.push(chunk.size as u32);
+        }
+    }
+
+    /// Offset and length of every chunk in the file.
+    pub fn chunk_spans(&self) -> Vec<(u64, usize)> {
+        let mut offset = 0;
+        (0..self.chunks.len())
+            .map(|i| {
+                let len = match self.chunking {
+                    Chunking::Fixed => {
+                        (self.file_size.saturating_sub(offset)).min(self.chunk_size as u64) as usize
+                    }
+                    Chunking::Cdc { .. } => self.chunk_sizes.get(i).copied().unwrap_or(0) as usize,
+                };
+                let span = (offset, len);
+                offset += len as u64;
+                span
+            })
+            .collect()
+    }
+
+    /// Byte range of chunk `index` within the file.
+    pub fn chunk_range(&self, index: usize) -> Option<std::ops::Range<u64>> {
+        let (offset, len) = *self.chunk_spans().get(index)?;
+        Some(offset..offset + len as u64)
+    }
+
+    /// Chunks `path` the same way this manifest's file was chunked.
+    pub fn chunk_reader(&self, path: &Path) -> Result<ChunkReader> {
+        ChunkReader::open_with(path, self.chunk_size, self.chunking)
+    }
+
+    /// The `.part` writer for downloading this file to `path`; fails if the
+    /// recorded chunk layout does not add up to `file_size`.
+    pub fn chunk_writer(&self, path: &Path) -> Result<ChunkWriter> {
+        self.chunking.validate()?;
+        if self.chunking.is_fixed() && self.chunk_size == 0 && self.file_size > 0 {
+            return Err(SyncError::Other("manifest has a chunk size of 0".into()));
         }
+        if !self.chunking.is_fixed() && self.chunk_sizes.len() != self.chunks.len() {
+            return Err(SyncError::Other(format!(
+                "manifest lists {} chunks but {} chunk sizes",
+                self.chunks.len(),
+                self.chunk_sizes.len()
+            )));
+        }
+        ChunkWriter::with_spans(path, self.file_size, self.chunk_spans())
+    }
+
+    pub fn from_chunks(file_name: String, chunk_size: usize, chunks: &[Chunk]) -> Self {
+        let mut manifest = Manifest::new(file_name, chunk_size);
+        for chunk in chunks {
+            manifest.push_chunk(chunk);
+        }
+        manifest.set_root();
+        manifest
+    }
+
+    /// `(hash, size)` of every chunk, the leaves of the Merkle tree.
+    pub fn merkle_leaves(&self) -> Vec<([u8; 32], u64)> {
+        self.chunks
+            .iter()
+            .zip(self.chunk_spans())
+            .map(|(hash, (_, len))| (*hash, len as u64))
+            .collect()
+    }
+
+    pub fn merkle_tree(&self) -> MerkleTree {
+        MerkleTree::new(self.merkle_leaves())
+    }
+
+    pub fn content_id(&self) -> [u8; 32] {
+        let tree = self.merkle_tree();
+        merkle::content_id(self.file_size, tree.len(), &tree.root())
+    }
+
+    /// Records the content ID; call once every chunk has been pushed.
+    pub fn set_root(&mut self) {
+        self.root = Some(merkle::content_id_hex(&self.content_id()));
+    }
+
+    /// Whether the chunk list was left out and has to come from peers.
+    pub fn is_compact(&self) -> bool {
+        self.root.is_some() && self.chunks.is_empty() && self.file_size > 0
+    }
+
+    /// The same manifest without its chunk list, recording the content ID
+    /// the list is checked against when it is filled in again.
+    pub fn compact(&self) -> Manifest {
+        let root = self
+            .root
+            .clone()
+            .unwrap_or_else(|| merkle::content_id_hex(&self.content_id()));
+        Manifest {
+            root: Some(root),
+            chunks: Vec::new(),
+            chunk_sizes: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Fails if the manifest records a content ID its chunks do not match.
+    pub fn verify_root(&self) -> Result<()> {
+        let Some(root) = &self.root else {
+            return Ok(());
+        };
+        if merkle::parse_content_id(root) != Some(self.content_id()) {
+            return Err(SyncError::Other(format!(
+                "chunk list of {} does not match its content ID {root}",
+                self.file_name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fills in the chunk list of a compact manifest from `(hash, size)`
+    /// leaves, which must reproduce the recorded content ID and chunk layout.
+    pub fn fill_chunks(&mut self, leaves: &[([u8; 32], u64)]) -> Result<()> {
+        self.chunks = leaves.iter().map(|(h, _)| *h).collect();
+        self.chunk_sizes = if self.chunking.is_fixed() {
+            Vec::new()
+        } else {
+            leaves.iter().map(|(_, s)| *s as u32).collect()
+        };
+        let layout_matches = self
+            .chunk_spans()
+            .iter()
+            .zip(leaves)
+            .all(|((_, len), (_, size))| *len as u64 == *size)
+            && leaves.iter().map(|(_, s)| s).sum::<u64>() == self.file_size;
+        if !layout_matches {
+            return Err(SyncError::Other(format!(
+                "chunk sizes for {} do not fit its chunking",
+                self.file_name
+            )));
+        }
+        self.verify_root()
+    }
+
+    /// Bytes a publisher signs: the content ID, which pins down the chunk
+    /// list and size, plus the names and chunking the file is shared with.
+    pub fn signing_payload(&self) -> Result<Vec<u8>> {
+        let root = self
+            .root
+            .as_deref()
+            .and_then(merkle::parse_content_id)
+            .ok_or_else(|| SyncError::Other("manifest has no content ID to sign".into()))?;
+        let mut out = Vec::with_capacity(96 + self.share.len() + self.file_name.len());
+        out.extend_from_slice(b"P2RENT-MANIFEST");
+        out.extend_from_slice(&root);
+        out.extend_from_slice(&self.file_size.to_be_bytes());
+        out.extend_from_slice(&(self.chunk_size as u64).to_be_bytes());
+        match self.chunking {
+            Chunking::Fixed => out.push(0),
+            Chunking::Cdc { min, avg, max } => {
+                out.push(1);
+                for v in [min, avg, max] {
+                    out.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+        }
+        for field in [&self.share, &self.file_name] {
+            out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+        Ok(out)
+    }
+
+    /// Signs the manifest as published by `kp`, recording the content ID
+    /// first if it is missing.
+    pub fn sign(&mut self, kp: &NodeKeypair) -> Result<()> {
+        if self.root.is_none() {
+            self.set_root();
+        }
+        let sig = crypto::sign(kp, &self.signing_payload()?)?;
+        self.publisher = Some(general_purpose::STANDARD.encode(kp.verifying.to_bytes()));
+        self.signature = Some(general_purpose::STANDARD.encode(sig));
+        Ok(())
+    }
+
+    /// Checks the publisher signature and returns the publisher's node id,
+    /// or `None` for an unsigned manifest. Only the content ID is signed, so
+    /// the chunk list must still be checked with [`Manifest::verify_root`].
+    pub fn verify_signature(&self) -> Result<Option<NodeId>> {
+        let (publisher, signature) = match (&self.publisher, &self.signature) {
+            (None, None) => return Ok(None),
+            (Some(p), Some(s)) => (p, s),
+            _ => {
+                return Err(SyncError::Other(format!(
+                    "manifest of {} has a publisher or a signature but not both",
+                    self.file_name
+                )));
+            }
+        };
+        let publisher: [u8; 32] = general_purpose::STANDARD.decode(publisher)?[..].try_into()?;
+        let signature: [u8; 64] = general_purpose::STANDARD.decode(signature)?[..].try_into()?;
+        if !crypto::verify(&publisher, &self.signing_payload()?, &signature)? {
+            return Err(SyncError::Other(format!(
+                "manifest of {} has an invalid publisher signature",
+                self.file_name
+            )));
+        }
+        Ok(Some(crypto::node_id_from_pubkey(&publisher)))
+    }
+
+    /// The id peers know this file by. Older manifests were keyed on the
+    /// file stem.
+    pub fn share_id(&self) -> String {
+        if !self.share.is_empty() {
+            return self.share.clone();
+        }
+        Path::new(&self.file_name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("file")
+            .to_string()
+    }
+
+    /// BLAKE3 of the serialized manifest, as hex.
+    pub fn digest(&self) -> String {
+        let data = serde_json::to_vec(self).expect("manifest serializes");
+        blake3::hash(&data).to_hex().to_string()
     }
 }
 
+/// A shared directory: every file under it with its relative path and basic
+/// metadata, plus the directories that contain nothing at all.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DirectoryManifest {
+    /// Share id of the directory itself; its files' ids start with it.
+    pub name: String,
+    pub files: Vec<DirectoryEntry>,
+    #[serde(default)]
+    pub empty_dirs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DirectoryEntry {
+    /// Path relative to the shared directory, `/`-separated.
+    pub path: String,
+    /// Unix permission bits.
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    pub manifest: Manifest,
+}
+
+impl DirectoryManifest {
+    /// Builds the directory manifest for `root` from the manifests of the
+    /// files found under it, reading their metadata from disk.
+    pub fn new(root: &Path, files: Vec<(PathBuf, Manifest)>) -> Result<Self> {
+        let mut entries = Vec::with_capacity(files.len());
+        for (file, manifest) in files {
+            let meta = fs::metadata(&file)?;
+            entries.push(DirectoryEntry {
+                path: relative_path(root, &file)?,
+                mode: meta.permissions().mode() & 0o7777,
+                mtime: meta
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+                manifest,
+            });
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let empty_dirs = scanner::scan_empty_dirs(root)?
+            .iter()
+            .map(|d| relative_path(root, d))
+            .collect::<Result<_>>()?;
+        Ok(DirectoryManifest {
+            name: share_id_for(root, root),
+            files: entries,
+            empty_dirs,
+        })
+    }
+
+    /// Rejects entries that would land outside the output directory.
+    pub fn validate(&self) -> Result<()> {
+        let bad = self
+            .files
+            .iter()
+            .map(|f| &f.path)
+            .chain(&self.empty_dirs)
+            .find(|p| !is_safe_relative(p));
+        match bad {
+            Some(p) => Err(SyncError::Other(format!(
+                "directory manifest has unsafe path {p:?}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if an entry's path is not the one its file manifest is shared
+    /// under, so signed file manifests also pin down the tree layout.
+    pub fn check_paths(&self) -> Result<()> {
+        match self
+            .files
+            .iter()
+            .find(|f| f.manifest.share != format!("{}/{}", self.name, f.path))
+        {
+            Some(f) => Err(SyncError::Other(format!(
+                "directory entry {:?} does not match its manifest's share id {:?}",
+                f.path, f.manifest.share
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|f| f.manifest.file_size).sum()
+    }
+
+    /// The same directory with every file manifest compact, so it stays
+    /// small however many chunks the files have.
+    pub fn compact(&self) -> DirectoryManifest {
+        DirectoryManifest {
+            name: self.name.clone(),
+            files: self
+                .files
+                .iter()
+                .map(|f| DirectoryEntry {
+                    path: f.path.clone(),
+                    mode: f.mode,
+                    mtime: f.mtime,
+                    manifest: f.manifest.compact(),
+                })
+                .collect(),
+            empty_dirs: self.empty_dirs.clone(),
+        }
+    }
+}
+
+impl DirectoryEntry {
+    /// Restores the recorded permissions and modification time on `path`.
+    /// Only the permission bits are applied: the manifest may come from a
+    /// peer, so setuid, setgid and sticky bits are dropped.
+    pub fn apply_metadata(&self, path: &Path) -> Result<()> {
+        let mode = self.mode & 0o777;
+        // Setting times only takes ownership, not write access, but the
+        // file has to be opened; make sure the owner can read it until the
+        // final mode goes on. Changing the mode leaves the mtime alone.
+        fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o400))?;
+        let file = fs::File::open(path)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(self.mtime))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        Ok(())
+    }
+}
+
+/// Which publishers a fetch accepts manifests from.
+#[derive(Debug, Clone, Default)]
+pub struct PublisherPolicy {
+    /// Refuse unsigned manifests.
+    pub require_signed: bool,
+    /// Node ids of accepted publishers; empty accepts any valid signature.
+    pub trusted: Vec<NodeId>,
+}
+
+impl PublisherPolicy {
+    /// Verifies `manifest`'s signature, if any, and applies the policy.
+    /// A bad signature is always an error. Returns the publisher's node id.
+    pub fn check(&self, manifest: &Manifest) -> Result<Option<NodeId>> {
+        let publisher = manifest.verify_signature()?;
+        match &publisher {
+            None if self.require_signed || !self.trusted.is_empty() => Err(SyncError::Other(
+                format!("manifest of {} is not signed", manifest.file_name),
+            )),
+            Some(id) if !self.trusted.is_empty() && !self.trusted.contains(id) => {
+                Err(SyncError::Other(format!(
+                    "manifest of {} is signed by untrusted publisher {id}",
+                    manifest.file_name
+                )))
+            }
+            _ => Ok(publisher),
+        }
+    }
+}
+
+/// Either kind of manifest, as found behind a `--manifest` path.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum AnyManifest {
+    Directory(DirectoryManifest),
+    File(Manifest),
+}
+
+fn relative_path(root: &Path, path: &Path) -> Result<String> {
+    let rel = path.strip_prefix(root).map_err(|_| {
+        SyncError::Other(format!(
+            "{} is not under {}",
+            path.display(),
+            root.display()
+        ))
+    })?;
+    let parts: Vec<_> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+/// Whether `path` is a non-empty relative path that stays below the
+/// directory it is joined to.
+pub fn is_safe_relative(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Share id for `file` found while sharing `root`: its path relative to the
+/// directory containing `root`, with `/` separators. A single file is shared
+/// under its file name; files of a shared directory keep the directory name
+/// as their first component, e.g. `photos/2024/a.jpg`.
+pub fn share_id_for(root: &Path, file: &Path) -> String {
+    let root_name = root
+        .file_name()
+        .map(|n| n.to_owned())
+        .or_else(|| root.canonicalize().ok()?.file_name().map(|n| n.to_owned()));
+    let mut parts: Vec<String> = root_name
+        .iter()
+        .map(|n| n.to_string_lossy().into_owned())
+        .collect();
+    if let Ok(rel) = file.strip_prefix(root) {
+        parts.extend(
+            rel.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+        );
+    }
+    parts.join("/")
+}
+
+/// Where the manifest for `share` lives under `dir`; nested ids get nested
+/// directories.
+pub fn manifest_path(dir: &Path, share: &str) -> PathBuf {
+    dir.join(format!("{share}.manifest.json"))
+}
+
+/// Where the directory manifest for the shared directory `name` lives.
+pub fn directory_manifest_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.dir.json"))
+}
+
 pub fn write_manifest(manifest: &Manifest, path: &Path) -> Result<()> {
     let data = serde_json::to_string_pretty(manifest)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
     std::fs::write(path, data)?;
     Ok(())
 }
@@ -37,3 +528,94 @@ pub fn read_manifest(path: &Path) -> Result<Manifest> {
     let manifest = serde_json::from_str(&data)?;
     Ok(manifest)
 }
+
+/// Fails if `path` already holds the manifest of different content, so a
+/// share id is never silently pointed at another file.
+pub fn check_replace(path: &Path, manifest: &Manifest) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let existing = read_manifest(path)?;
+    if existing.content_id() != manifest.content_id() {
+        return Err(SyncError::Other(format!(
+            "{} already describes different content ({}) under share {:?}",
+            path.display(),
+            merkle::content_id_hex(&existing.content_id()),
+            existing.share
+        )));
+    }
+    Ok(())
+}
+
+/// Like [`check_replace`] for a directory manifest: its files, by path and
+/// content, must be the ones already recorded at `path`.
+pub fn check_replace_directory(path: &Path, manifest: &DirectoryManifest) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let AnyManifest::Directory(existing) = read_any_manifest(path)? else {
+        return Err(SyncError::Other(format!(
+            "{} is not a directory manifest",
+            path.display()
+        )));
+    };
+    let layout = |d: &DirectoryManifest| {
+        d.files
+            .iter()
+            .map(|f| (f.path.clone(), f.manifest.content_id()))
+            .collect::<Vec<_>>()
+    };
+    if layout(&existing) != layout(manifest) {
+        return Err(SyncError::Other(format!(
+            "{} already describes a different tree under share {:?}",
+            path.display(),
+            existing.name
+        )));
+    }
+    Ok(())
+}
+
+pub fn write_directory_manifest(manifest: &DirectoryManifest, path: &Path) -> Result<()> {
+    let data = serde_json::to_string_pretty(manifest)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// Reads a manifest that may describe either a single file or a directory.
+pub fn read_any_manifest(path: &Path) -> Result<AnyManifest> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+/// All `*.manifest.json` files under `dir`, in path order. A missing
+/// directory has no manifests.
+pub fn list_manifests(dir: &Path) -> Result<Vec<PathBuf>> {
+    list_ending_with(dir, ".manifest.json")
+}
+
+/// All `*.dir.json` directory manifests under `dir`, in path order.
+pub fn list_directory_manifests(dir: &Path) -> Result<Vec<PathBuf>> {
+    list_ending_with(dir, ".dir.json")
+}
+
+fn list_ending_with(dir: &Path, suffix: &str) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file()
+            && entry
+                .file_name()
+                .to_str()
+                .is_some_and(|n| n.ends_with(suffix))
+        {
+            paths.push(entry.into_path());
+        }
+    }
+    Ok(paths)
+}
@@ -0,0 +1,170 @@
+const LEAF: u8 = 0x00;
+const NODE: u8 = 0x01;
+const CONTENT: u8 = 0x02;
+
+pub fn leaf_hash(chunk_hash: &[u8; 32], size: u64) -> [u8; 32] {
+    let mut h = blake3::Hasher::new();
+    h.update(&[LEAF]);
+    h.update(chunk_hash);
+    h.update(&size.to_le_bytes());
+    h.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut h = blake3::Hasher::new();
+    h.update(&[NODE]);
+    h.update(left);
+    h.update(right);
+    h.finalize().into()
+}
+
+/// Tree root of a file with no chunks.
+fn empty_root() -> [u8; 32] {
+    blake3::hash(&[NODE]).into()
+}
+
+/// The content ID of a file: pins down the whole chunk list and the file's
+/// length in 32 bytes.
+pub fn content_id(file_size: u64, total: u64, tree_root: &[u8; 32]) -> [u8; 32] {
+    let mut h = blake3::Hasher::new();
+    h.update(&[CONTENT]);
+    h.update(&file_size.to_le_bytes());
+    h.update(&total.to_le_bytes());
+    h.update(tree_root);
+    h.finalize().into()
+}
+
+/// Parses a content ID given as 64 hex digits.
+pub fn parse_content_id(hex: &str) -> Option<[u8; 32]> {
+    blake3::Hash::from_hex(hex).ok().map(|h| *h.as_bytes())
+}
+
+pub fn content_id_hex(id: &[u8; 32]) -> String {
+    blake3::Hash::from_bytes(*id).to_hex().to_string()
+}
+
+/// Merkle tree over a file's chunk hashes, all levels kept, leaves first.
+///
+/// Leaves are `H(0x00 ‖ chunk hash ‖ chunk size)` and inner nodes
+/// `H(0x01 ‖ left ‖ right)`; a node without a sibling is carried up to the
+/// next level unchanged. Any contiguous run of leaves can be proven against
+/// the root with `O(log n)` sibling hashes.
+#[derive(Debug)]
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Builds the tree over `(chunk hash, chunk size)` pairs.
+    pub fn new(chunks: impl IntoIterator<Item = ([u8; 32], u64)>) -> Self {
+        let leaves: Vec<_> = chunks
+            .into_iter()
+            .map(|(hash, size)| leaf_hash(&hash, size))
+            .collect();
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [l, r] => node_hash(l, r),
+                    [only] => *only,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.levels[0].len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        match self.levels.last().unwrap().as_slice() {
+            [root] => *root,
+            _ => empty_root(),
+        }
+    }
+
+    /// Sibling hashes proving leaves `start..end`, lowest level first and,
+    /// within a level, the left sibling before the right one.
+    pub fn prove(&self, start: u64, end: u64) -> Vec<[u8; 32]> {
+        let mut proof = Vec::new();
+        if start >= end || end > self.len() {
+            return proof;
+        }
+        let (mut a, mut b) = (start as usize, end as usize);
+        for level in &self.levels[..self.levels.len() - 1] {
+            if a % 2 == 1 {
+                proof.push(level[a - 1]);
+                a -= 1;
+            }
+            if b % 2 == 1 && b < level.len() {
+                proof.push(level[b]);
+                b += 1;
+            }
+            a /= 2;
+            b = b.div_ceil(2);
+        }
+        proof
+    }
+}
+
+/// Checks that `leaves` (chunk hash and size pairs) are leaves
+/// `start..start + leaves.len()` of a `total`-leaf tree with root
+/// `tree_root`, given the siblings from [`MerkleTree::prove`].
+pub fn verify_range(
+    tree_root: &[u8; 32],
+    total: u64,
+    start: u64,
+    leaves: &[([u8; 32], u64)],
+    proof: &[[u8; 32]],
+) -> bool {
+    if total == 0 {
+        return leaves.is_empty() && proof.is_empty() && *tree_root == empty_root();
+    }
+    let Some(end) = start.checked_add(leaves.len() as u64) else {
+        return false;
+    };
+    if leaves.is_empty() || end > total {
+        return false;
+    }
+    let mut nodes: Vec<[u8; 32]> = leaves.iter().map(|(h, s)| leaf_hash(h, *s)).collect();
+    let mut proof = proof.iter();
+    let (mut a, mut b, mut width) = (start as usize, end as usize, total as usize);
+    while width > 1 {
+        if a % 2 == 1 {
+            let Some(left) = proof.next() else {
+                return false;
+            };
+            nodes.insert(0, *left);
+            a -= 1;
+        }
+        if b % 2 == 1 && b < width {
+            let Some(right) = proof.next() else {
+                return false;
+            };
+            nodes.push(*right);
+            b += 1;
+        }
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| match pair {
+                [l, r] => node_hash(l, r),
+                [only] => *only,
+                _ => unreachable!(),
+            })
+            .collect();
+        a /= 2;
+        b = b.div_ceil(2);
+        width = width.div_ceil(2);
+    }
+    proof.next().is_none() && nodes.len() == 1 && nodes[0] == *tree_root
+}
@@ -0,0 +1,736 @@
+use crate::bitfield::Bitfield;
+use crate::chunk::Chunking;
+use crate::error::{Result, SyncError};
+use crate::manifest::{AnyManifest, Manifest};
+use crate::merkle;
+use crate::net::protocol::{
+    MAX_HASHES_PER_REPLY, MAX_SHARES_PER_REPLY, ManifestQuery, Message, ShareInfo,
+};
+use crate::net::quic::{self, Peer};
+use crate::outboard::{self, SliceVerifier};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, mpsc};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+/// Requests kept in flight per peer unless overridden with [`Downloader::window`].
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// How long a peer gets to answer one request unless overridden with
+/// [`Downloader::timeout`].
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Pulls the chunks of one manifest from a set of peers.
+///
+/// Each peer is first asked which of the needed chunks it holds (`Need` /
+/// `Have`); chunks themselves are then requested by digest and streamed as
+/// verified slices, so a peer sending bad data is caught within a few KiB.
+/// It then gets `window` workers, each on its own QUIC stream, that
+/// take the next pending chunk it can serve from a shared queue, so faster
+/// peers naturally serve more chunks. A chunk that fails or does not match
+/// its manifest digest goes back on the queue and is not offered to the same
+/// peer again. A peer that lets a request run past the timeout gets no more
+/// requests, and once nothing is left to hand out idle peers also ask for
+/// the chunks still in flight elsewhere; whichever copy arrives first is
+/// used.
+pub struct Downloader {
+    peers: Vec<Peer>,
+    share: String,
+    hashes: Vec<[u8; 32]>,
+    sizes: Vec<u64>,
+    window: usize,
+    timeout: Duration,
+    completed: Option<Bitfield>,
+}
+
+struct Schedule {
+    pending: VecDeque<u64>,
+    /// Peers that already failed each chunk, indexed by chunk.
+    failed: Vec<HashSet<usize>>,
+    /// Chunks requested but not delivered yet, with the peers asked.
+    in_flight: HashMap<u64, HashSet<usize>>,
+    /// Peers whose availability is not known yet.
+    starting: usize,
+    /// Peers that stopped answering.
+    stalled: HashSet<usize>,
+}
+
+struct Shared {
+    share: String,
+    hashes: Vec<[u8; 32]>,
+    sizes: Vec<u64>,
+    timeout: Duration,
+    schedule: Mutex<Schedule>,
+    notify: Notify,
+}
+
+impl Shared {
+    /// Next chunk for `peer` among those it `has`, waiting while other
+    /// peers may still hand work back. `None` once nothing is left that this
+    /// peer could serve.
+    async fn next_for(&self, peer: usize, has: &Bitfield) -> Option<u64> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut guard = self.schedule.lock().unwrap();
+                let s = &mut *guard;
+                if s.stalled.contains(&peer) {
+                    return None;
+                }
+                let pos = s
+                    .pending
+                    .iter()
+                    .position(|&i| has.get(i) && !s.failed[i as usize].contains(&peer));
+                if let Some(pos) = pos {
+                    let index = s.pending.remove(pos).unwrap();
+                    s.in_flight.entry(index).or_default().insert(peer);
+                    return Some(index);
+                }
+                // Endgame: help with a chunk another peer is still on.
+                let duplicate = s.in_flight.iter_mut().find(|(i, asked)| {
+                    has.get(**i)
+                        && !asked.contains(&peer)
+                        && !s.failed[**i as usize].contains(&peer)
+                });
+                if let Some((&index, asked)) = duplicate {
+                    asked.insert(peer);
+                    return Some(index);
+                }
+                if s.in_flight.is_empty() && s.starting == 0 {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    fn started(&self) {
+        self.schedule.lock().unwrap().starting -= 1;
+        self.notify.notify_waiters();
+    }
+
+    /// Marks `index` delivered; `false` if another peer delivered it first.
+    fn complete(&self, index: u64) -> bool {
+        let first = self
+            .schedule
+            .lock()
+            .unwrap()
+            .in_flight
+            .remove(&index)
+            .is_some();
+        self.notify.notify_waiters();
+        first
+    }
+
+    /// Hands `index` back after `peer` failed it, unless another peer is
+    /// still working on it or has delivered it.
+    fn retry(&self, index: u64, peer: usize) {
+        {
+            let mut s = self.schedule.lock().unwrap();
+            s.failed[index as usize].insert(peer);
+            if let Some(asked) = s.in_flight.get_mut(&index) {
+                asked.remove(&peer);
+                if asked.is_empty() {
+                    s.in_flight.remove(&index);
+                    s.pending.push_back(index);
+                }
+            }
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Stops handing chunks to `peer`.
+    fn stall(&self, peer: usize) {
+        self.schedule.lock().unwrap().stalled.insert(peer);
+        self.notify.notify_waiters();
+    }
+}
+
+impl Downloader {
+    pub fn new(peers: Vec<Peer>, share: String, manifest: &Manifest) -> Self {
+        Self {
+            peers,
+            share,
+            hashes: manifest.chunks.clone(),
+            sizes: manifest
+                .chunk_spans()
+                .into_iter()
+                .map(|(_, size)| size as u64)
+                .collect(),
+            window: DEFAULT_WINDOW,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            completed: None,
+        }
+    }
+
+    /// Marks chunks the caller already holds; only the rest are requested.
+    pub fn completed(mut self, completed: Bitfield) -> Self {
+        self.completed = Some(completed);
+        self
+    }
+
+    /// Sets how many chunk requests may be in flight to each peer at once.
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Sets how long a peer may take to answer one request before its chunk
+    /// goes to another peer and it is asked for nothing more.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Downloads every missing chunk and hands each verified one to `on_chunk`.
+    ///
+    /// Chunks arrive in completion order, not index order; callers place
+    /// them by index.
+    pub async fn run<F>(self, mut on_chunk: F) -> Result<()>
+    where
+        F: FnMut(u64, Vec<u8>) -> Result<()>,
+    {
+        let total = self.hashes.len();
+        let mut need = Bitfield::new(total as u64);
+        for index in 0..total as u64 {
+            if !self.completed.as_ref().is_some_and(|c| c.get(index)) {
+                need.set(index);
+            }
+        }
+        let mut remaining = need.count_ones() as usize;
+        if remaining == 0 {
+            return Ok(());
+        }
+        if self.peers.is_empty() {
+            return Err(SyncError::Other("no peers to download from".into()));
+        }
+
+        let need = Arc::new(need);
+        let shared = Arc::new(Shared {
+            share: self.share,
+            hashes: self.hashes,
+            sizes: self.sizes,
+            timeout: self.timeout,
+            schedule: Mutex::new(Schedule {
+                pending: need.iter_ones().collect(),
+                failed: vec![HashSet::new(); total],
+                in_flight: HashMap::new(),
+                starting: self.peers.len(),
+                stalled: HashSet::new(),
+            }),
+            notify: Notify::new(),
+        });
+
+        let (tx, mut rx) = mpsc::channel(self.peers.len() * self.window);
+        let mut peers = JoinSet::new();
+        for (peer_idx, peer) in self.peers.into_iter().enumerate() {
+            peers.spawn(run_peer(
+                peer_idx,
+                peer,
+                self.window,
+                need.clone(),
+                shared.clone(),
+                tx.clone(),
+            ));
+        }
+        drop(tx);
+
+        let mut missing = (*need).clone();
+        while let Some((index, data)) = rx.recv().await {
+            missing.unset(index);
+            on_chunk(index, data)?;
+            remaining -= 1;
+            if remaining == 0 {
+                return Ok(());
+            }
+        }
+
+        let first = missing.iter_ones().next().unwrap_or(0);
+        Err(SyncError::Other(format!(
+            "{remaining} chunk(s) unavailable from all peers (first missing: {first})"
+        )))
+    }
+}
+
+async fn run_peer(
+    peer_idx: usize,
+    peer: Peer,
+    window: usize,
+    need: Arc<Bitfield>,
+    shared: Arc<Shared>,
+    tx: mpsc::Sender<(u64, Vec<u8>)>,
+) {
+    let query = query_availability(&peer, &shared.share, &need);
+    let has = match timeout(shared.timeout, query).await {
+        Ok(Ok(has)) => has,
+        // Nothing is asked of a peer that does not say what it holds.
+        _ => Bitfield::new(need.len()),
+    };
+    shared.started();
+    if has.count_ones() == 0 {
+        return;
+    }
+    let has = Arc::new(has);
+
+    let mut workers = JoinSet::new();
+    for _ in 0..window {
+        workers.spawn(run_worker(
+            peer_idx,
+            peer.clone(),
+            has.clone(),
+            shared.clone(),
+            tx.clone(),
+        ));
+    }
+    while workers.join_next().await.is_some() {}
+}
+
+async fn run_worker(
+    peer_idx: usize,
+    peer: Peer,
+    has: Arc<Bitfield>,
+    shared: Arc<Shared>,
+    tx: mpsc::Sender<(u64, Vec<u8>)>,
+) {
+    while let Some(index) = shared.next_for(peer_idx, &has).await {
+        let hash = &shared.hashes[index as usize];
+        let size = shared.sizes.get(index as usize).copied().unwrap_or(0);
+        match timeout(shared.timeout, request_verified(&peer, hash, size)).await {
+            Ok(Ok(data)) => {
+                if shared.complete(index) && tx.send((index, data)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(Err(_)) => {
+                shared.retry(index, peer_idx);
+                if peer.connection.close_reason().is_some() {
+                    break;
+                }
+            }
+            Err(_) => {
+                // The peer keeps the stream open without answering.
+                shared.retry(index, peer_idx);
+                shared.stall(peer_idx);
+                break;
+            }
+        }
+    }
+    // This peer is done; others waiting on its in-flight work re-check.
+    shared.notify.notify_waiters();
+}
+
+/// Sends our `Need` bitfield and returns the peer's `Have` reply, limited to
+/// the chunks we asked about.
+pub async fn query_availability(peer: &Peer, share: &str, need: &Bitfield) -> Result<Bitfield> {
+    let (mut send, mut recv) = peer.connection.open_bi().await?;
+    let req = Message::Need {
+        share: share.to_string(),
+        chunks: need.clone(),
+    };
+    quic::send_message(&mut send, &req).await?;
+    match quic::receive_message(&mut recv).await? {
+        Message::Have { chunks, .. } if chunks.is_well_formed() && chunks.len() == need.len() => {
+            let mut has = Bitfield::new(need.len());
+            for index in need.iter_ones().filter(|&i| chunks.get(i)) {
+                has.set(index);
+            }
+            Ok(has)
+        }
+        _ => Err(SyncError::Other(
+            "peer did not answer availability query".into(),
+        )),
+    }
+}
+
+async fn request_chunk(peer: &Peer, hash: &[u8; 32]) -> Result<Vec<u8>> {
+    let (mut send, mut recv) = peer.connection.open_bi().await?;
+    let req = Message::RequestChunkByHash { hash: *hash };
+    quic::send_message(&mut send, &req).await?;
+    match quic::receive_message(&mut recv).await? {
+        Message::ChunkByHash { hash: h, data } if h == *hash => Ok(data),
+        _ => Err(SyncError::Other(format!(
+            "unexpected reply to request for chunk {}",
+            blake3::Hash::from_bytes(*hash).to_hex()
+        ))),
+    }
+}
+
+/// Fetches a whole chunk of `size` bytes, streamed and checked group by
+/// group when the peer serves slices and otherwise checked once complete.
+async fn request_verified(peer: &Peer, hash: &[u8; 32], size: u64) -> Result<Vec<u8>> {
+    if size > 0
+        && let Some(data) = request_slice(peer, hash, size, 0, size).await?
+    {
+        return Ok(data);
+    }
+    let data = request_chunk(peer, hash).await?;
+    if <[u8; 32]>::from(blake3::hash(&data)) != *hash {
+        return Err(SyncError::Other(format!(
+            "chunk {} failed verification",
+            blake3::Hash::from_bytes(*hash).to_hex()
+        )));
+    }
+    Ok(data)
+}
+
+/// Streams bytes `start..end` of the `len`-byte chunk `hash` from `peer` and
+/// returns exactly those bytes. Each group is checked against `hash` as it
+/// arrives and the transfer is abandoned at the first bad one.
+pub async fn fetch_slice(
+    peer: &Peer,
+    hash: &[u8; 32],
+    len: u64,
+    start: u64,
+    end: u64,
+) -> Result<Vec<u8>> {
+    request_slice(peer, hash, len, start, end)
+        .await?
+        .ok_or_else(|| {
+            SyncError::Other(format!(
+                "peer did not serve a slice of chunk {}",
+                blake3::Hash::from_bytes(*hash).to_hex()
+            ))
+        })
+}
+
+/// Like [`fetch_slice`], but `None` when the peer does not answer with a
+/// slice at all (it lacks the chunk or predates slices).
+async fn request_slice(
+    peer: &Peer,
+    hash: &[u8; 32],
+    len: u64,
+    start: u64,
+    end: u64,
+) -> Result<Option<Vec<u8>>> {
+    if start >= end || end > len {
+        return Err(SyncError::Other(format!(
+            "range {start}..{end} is not inside a {len}-byte chunk"
+        )));
+    }
+    let (mut send, mut recv) = peer.connection.open_bi().await?;
+    let req = Message::RequestSlice {
+        hash: *hash,
+        start,
+        end,
+    };
+    quic::send_message(&mut send, &req).await?;
+    let Ok(Message::Slice {
+        hash: h,
+        len: l,
+        start: s,
+        end: e,
+        nodes,
+    }) = quic::receive_header(&mut recv).await
+    else {
+        return Ok(None);
+    };
+    let (group_start, group_end) = outboard::group_range(len, start, end);
+    if h != *hash || l != len || s != group_start || e != group_end {
+        return Err(SyncError::Other(
+            "slice reply does not match request".into(),
+        ));
+    }
+    let mut verifier = SliceVerifier::new(hash, len, start, end, &nodes)?;
+    let mut data = Vec::with_capacity((group_end - group_start) as usize);
+    while let Some(n) = verifier.next_len() {
+        let at = data.len();
+        data.resize(at + n, 0);
+        recv.read_exact(&mut data[at..]).await?;
+        if let Err(e) = verifier.verify(&data[at..]) {
+            let _ = recv.stop(0u32.into());
+            return Err(e);
+        }
+    }
+    data.drain(..(start - group_start) as usize);
+    data.truncate((end - start) as usize);
+    Ok(Some(data))
+}
+
+/// Reads bytes `start..end` of the file described by `manifest`, fetching
+/// only the verification groups that cover them. Each piece comes from the
+/// first peer that serves it verified.
+pub async fn read_range(
+    peers: &[Peer],
+    manifest: &Manifest,
+    start: u64,
+    end: u64,
+) -> Result<Vec<u8>> {
+    if start > end || end > manifest.file_size {
+        return Err(SyncError::Other(format!(
+            "range {start}..{end} is not inside a {}-byte file",
+            manifest.file_size
+        )));
+    }
+    let spans = manifest.chunk_spans();
+    if spans.len() != manifest.chunks.len() {
+        return Err(SyncError::Other("manifest has no chunk list".into()));
+    }
+    let mut out = Vec::with_capacity((end - start) as usize);
+    for ((offset, size), hash) in spans.into_iter().zip(&manifest.chunks) {
+        let size = size as u64;
+        if offset + size <= start || offset >= end {
+            continue;
+        }
+        let (a, b) = (start.max(offset) - offset, end.min(offset + size) - offset);
+        let mut piece = None;
+        for peer in peers {
+            if let Ok(data) = fetch_slice(peer, hash, size, a, b).await {
+                piece = Some(data);
+                break;
+            }
+        }
+        let mut piece = piece.ok_or_else(|| {
+            SyncError::Other(format!(
+                "no peer served bytes {}..{} verified",
+                offset + a,
+                offset + b
+            ))
+        })?;
+        out.append(&mut piece);
+    }
+    Ok(out)
+}
+
+/// Fetches the manifest of the file with content ID `root` from the first
+/// peer that has one matching it. A manifest that lists its chunks must
+/// reproduce `root`; a compact one, as peers send them, gets its list from
+/// [`complete_manifest`]. Its publisher signature is left to the caller.
+pub async fn fetch_manifest(peers: &[Peer], root: &[u8; 32]) -> Result<Manifest> {
+    match first_manifest(peers, &ManifestQuery::Root(*root)).await? {
+        AnyManifest::File(mut m) => {
+            complete_manifest(peers, &mut m).await?;
+            Ok(m)
+        }
+        AnyManifest::Directory(_) => unreachable!("checked in request_manifest"),
+    }
+}
+
+/// Fetches the file or directory manifest a peer serves under `share`.
+/// Nothing but its publisher signatures vouches for what comes back.
+pub async fn fetch_shared_manifest(peers: &[Peer], share: &str) -> Result<AnyManifest> {
+    let mut any = first_manifest(peers, &ManifestQuery::Share(share.to_string())).await?;
+    match &mut any {
+        AnyManifest::File(m) => complete_manifest(peers, m).await?,
+        AnyManifest::Directory(d) => {
+            for entry in &mut d.files {
+                complete_manifest(peers, &mut entry.manifest).await?;
+            }
+        }
+    }
+    Ok(any)
+}
+
+/// Fills in the chunk list of a compact manifest from `peers`, every batch
+/// proven against its content ID. Other manifests are left as they are.
+pub async fn complete_manifest(peers: &[Peer], manifest: &mut Manifest) -> Result<()> {
+    if !manifest.is_compact() {
+        return Ok(());
+    }
+    let hex = manifest.root.clone().unwrap_or_default();
+    let id = merkle::parse_content_id(&hex)
+        .ok_or_else(|| SyncError::Other(format!("invalid content ID {hex:?}")))?;
+    let list = fetch_chunk_list(peers, &id).await?;
+    if list.file_size != manifest.file_size {
+        return Err(SyncError::Other(format!(
+            "peers describe {hex} as {} bytes, the manifest says {}",
+            list.file_size, manifest.file_size
+        )));
+    }
+    manifest.fill_chunks(&list.leaves)
+}
+
+async fn first_manifest(peers: &[Peer], query: &ManifestQuery) -> Result<AnyManifest> {
+    for peer in peers {
+        if let Ok(m) = request_manifest(peer, query).await {
+            return Ok(m);
+        }
+    }
+    Err(SyncError::Other(match query {
+        ManifestQuery::Root(root) => format!(
+            "no peer returned a manifest for {}",
+            merkle::content_id_hex(root)
+        ),
+        ManifestQuery::Share(share) => format!("no peer serves a share named {share:?}"),
+    }))
+}
+
+async fn request_manifest(peer: &Peer, query: &ManifestQuery) -> Result<AnyManifest> {
+    let (mut send, mut recv) = peer.connection.open_bi().await?;
+    let req = Message::GetManifest {
+        query: query.clone(),
+    };
+    quic::send_message(&mut send, &req).await?;
+    let Message::ManifestData { manifest } = quic::receive_message(&mut recv).await? else {
+        return Err(SyncError::Other("peer has no such manifest".into()));
+    };
+    let any: AnyManifest = serde_json::from_slice(&manifest)?;
+    if let ManifestQuery::Root(root) = query {
+        let AnyManifest::File(m) = &any else {
+            return Err(SyncError::Other(
+                "peer sent a directory for a content ID".into(),
+            ));
+        };
+        if m.root.as_deref().and_then(merkle::parse_content_id) != Some(*root) {
+            return Err(SyncError::Other("manifest names another content ID".into()));
+        }
+        if !m.is_compact() {
+            m.verify_root()?;
+        }
+    }
+    Ok(any)
+}
+
+/// Everything `peer` serves, fetched page by page.
+pub async fn list_shares(peer: &Peer) -> Result<Vec<ShareInfo>> {
+    let mut all = Vec::new();
+    loop {
+        let (mut send, mut recv) = peer.connection.open_bi().await?;
+        let req = Message::ListShares {
+            start: all.len() as u64,
+            count: MAX_SHARES_PER_REPLY,
+        };
+        quic::send_message(&mut send, &req).await?;
+        let Message::Shares {
+            total,
+            start,
+            mut shares,
+        } = quic::receive_message(&mut recv).await?
+        else {
+            return Err(SyncError::Other("peer does not list its shares".into()));
+        };
+        if start != all.len() as u64 {
+            return Err(SyncError::Other(
+                "peer sent the wrong page of shares".into(),
+            ));
+        }
+        // The catalog can change between pages; stop at an empty page.
+        let done = shares.is_empty();
+        all.append(&mut shares);
+        if done || all.len() as u64 >= total {
+            return Ok(all);
+        }
+    }
+}
+
+/// Chunk list of a file fetched by content ID, every batch proven against it.
+#[derive(Debug)]
+pub struct ChunkList {
+    pub file_size: u64,
+    pub chunk_size: usize,
+    pub chunking: Chunking,
+    /// `(hash, size)` of each chunk in order.
+    pub leaves: Vec<([u8; 32], u64)>,
+}
+
+/// Size and tree root a peer claims for a content ID, fixed by its first
+/// verified reply.
+#[derive(PartialEq)]
+struct ListHeader {
+    tree_root: [u8; 32],
+    file_size: u64,
+    total: u64,
+    chunk_size: u64,
+    cdc: Option<[u32; 3]>,
+}
+
+/// Fetches the whole chunk list of the file with content ID `root`, in
+/// batches of [`MAX_HASHES_PER_REPLY`]. Each batch must come with a Merkle
+/// proof against `root`; a peer whose reply does not verify is not asked
+/// again and the batch goes to the next one.
+pub async fn fetch_chunk_list(peers: &[Peer], root: &[u8; 32]) -> Result<ChunkList> {
+    let mut header: Option<ListHeader> = None;
+    let mut leaves = Vec::new();
+    let mut dropped = HashSet::new();
+    while header
+        .as_ref()
+        .is_none_or(|h| (leaves.len() as u64) < h.total)
+    {
+        let start = leaves.len() as u64;
+        let mut batch = None;
+        for (i, peer) in peers.iter().enumerate() {
+            if dropped.contains(&i) {
+                continue;
+            }
+            match request_hashes(peer, root, start, header.as_ref()).await {
+                Ok(reply) => {
+                    batch = Some(reply);
+                    break;
+                }
+                Err(_) => {
+                    dropped.insert(i);
+                }
+            }
+        }
+        let Some((h, mut more)) = batch else {
+            return Err(SyncError::Other(format!(
+                "no peer returned a verified chunk list for {}",
+                merkle::content_id_hex(root)
+            )));
+        };
+        header = Some(h);
+        leaves.append(&mut more);
+    }
+
+    let h = header.expect("loop ran at least once");
+    let chunking = match h.cdc {
+        None => Chunking::Fixed,
+        Some([min, avg, max]) => Chunking::Cdc { min, avg, max },
+    };
+    chunking.validate()?;
+    Ok(ChunkList {
+        file_size: h.file_size,
+        chunk_size: usize::try_from(h.chunk_size)
+            .map_err(|_| SyncError::Other("chunk size out of range".into()))?,
+        chunking,
+        leaves,
+    })
+}
+
+/// One verified `GetHashes` round trip starting at `start`.
+async fn request_hashes(
+    peer: &Peer,
+    root: &[u8; 32],
+    start: u64,
+    known: Option<&ListHeader>,
+) -> Result<(ListHeader, Vec<([u8; 32], u64)>)> {
+    let (mut send, mut recv) = peer.connection.open_bi().await?;
+    let req = Message::GetHashes {
+        root: *root,
+        start,
+        count: MAX_HASHES_PER_REPLY,
+    };
+    quic::send_message(&mut send, &req).await?;
+    let Message::Hashes {
+        root: r,
+        tree_root,
+        file_size,
+        total,
+        chunk_size,
+        cdc,
+        start: s,
+        leaves,
+        proof,
+    } = quic::receive_message(&mut recv).await?
+    else {
+        return Err(SyncError::Other("peer does not serve chunk lists".into()));
+    };
+    let header = ListHeader {
+        tree_root,
+        file_size,
+        total,
+        chunk_size,
+        cdc,
+    };
+    let verified = r == *root
+        && s == start
+        && merkle::content_id(file_size, total, &tree_root) == *root
+        && known.is_none_or(|k| *k == header)
+        && (total == 0 || !leaves.is_empty())
+        && merkle::verify_range(&tree_root, total, start, &leaves, &proof);
+    if !verified {
+        return Err(SyncError::Other("chunk list proof did not verify".into()));
+    }
+    Ok((header, leaves))
+}
@@ -1,2 +1,5 @@
+pub mod download;
 pub mod protocol;
+pub mod push;
 pub mod quic;
+pub mod server;
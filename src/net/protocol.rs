@@ -1,4 +1,37 @@
+use crate::bitfield::Bitfield;
 use serde::{Deserialize, Serialize};
+
+/// Most chunk hashes a `Hashes` reply carries; larger requests are cut short.
+pub const MAX_HASHES_PER_REPLY: u64 = 4096;
+
+/// What a `GetManifest` request names.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ManifestQuery {
+    /// The file with this content ID.
+    Root([u8; 32]),
+    /// A file or directory by share id, e.g. `photos/2024/a.jpg` or `photos`.
+    Share(String),
+}
+
+/// Most entries a `Shares` reply carries.
+pub const MAX_SHARES_PER_REPLY: u64 = 100;
+
+/// One entry of a peer's catalog.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShareInfo {
+    /// Share id to fetch it by.
+    pub share: String,
+    pub directory: bool,
+    /// Total size in bytes; summed over all files for a directory.
+    pub size: u64,
+    pub chunks: u64,
+    /// Content ID of a file.
+    pub root: Option<[u8; 32]>,
+    /// Node id of the publisher whose signature the manifest carries; for
+    /// a directory, the one publisher of all its files, if there is one.
+    pub publisher: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message {
     Handshake {
@@ -6,18 +39,108 @@ pub enum Message {
         total_chunks: u64,
     },
     RequestChunk {
-        stem: String,
+        share: String,
         index: u64,
     },
+    /// Reply to `Need`: which of the requested chunks the sender holds.
     Have {
-        chunks: Vec<u64>,
+        share: String,
+        chunks: Bitfield,
     },
+    /// Availability query: the chunks of `share` the sender is missing.
     Need {
-        chunks: Vec<u64>,
+        share: String,
+        chunks: Bitfield,
     },
     Chunk {
         index: u64,
         data: Vec<u8>,
     },
+    /// Fetches a chunk from the peer's content-addressed store.
+    RequestChunkByHash {
+        hash: [u8; 32],
+    },
+    ChunkByHash {
+        hash: [u8; 32],
+        data: Vec<u8>,
+    },
+    /// Asks for up to `count` chunk hashes of the file with content ID
+    /// `root`, starting at chunk `start`.
+    GetHashes {
+        root: [u8; 32],
+        start: u64,
+        count: u64,
+    },
+    /// Reply to `GetHashes`: `(hash, size)` of chunks `start..`, with the
+    /// sibling hashes proving them against `tree_root`. `root` must equal
+    /// the content ID of `file_size`, `total` and `tree_root`.
+    Hashes {
+        root: [u8; 32],
+        tree_root: [u8; 32],
+        file_size: u64,
+        total: u64,
+        chunk_size: u64,
+        /// min/avg/max when the file uses content-defined chunking.
+        cdc: Option<[u32; 3]>,
+        start: u64,
+        leaves: Vec<([u8; 32], u64)>,
+        proof: Vec<[u8; 32]>,
+    },
+    /// Asks for a manifest by content ID or by the id it is shared under.
+    GetManifest {
+        query: ManifestQuery,
+    },
+    /// Reply to `GetManifest`: the file or directory manifest as JSON, as it
+    /// would be stored on disk (bincode cannot carry its tagged chunking
+    /// field).
+    ManifestData {
+        manifest: Vec<u8>,
+    },
+    /// Asks for up to `count` entries of the sender's catalog, starting at
+    /// entry `start`.
+    ListShares {
+        start: u64,
+        count: u64,
+    },
+    /// Reply to `ListShares`: entries `start..` of a `total`-entry catalog,
+    /// sorted by share id.
+    Shares {
+        total: u64,
+        start: u64,
+        shares: Vec<ShareInfo>,
+    },
+    /// Offers a file or directory manifest (JSON) to the receiver, which
+    /// pulls the chunks it lacks back over the same connection.
+    Push {
+        manifest: Vec<u8>,
+    },
+    /// Reply to `Push` once the content is stored: chunks pulled from the
+    /// sender and chunks the receiver already held.
+    Pushed {
+        fetched: u64,
+        present: u64,
+    },
+    /// Reply to `Push` when the receiver will not take the content.
+    Refused {
+        reason: String,
+    },
+    /// Asks for bytes `start..end` of the chunk with digest `hash`, with
+    /// what is needed to verify them as they arrive.
+    RequestSlice {
+        hash: [u8; 32],
+        start: u64,
+        end: u64,
+    },
+    /// Reply to `RequestSlice`, sent as a length-prefixed header and followed
+    /// on the stream by the raw bytes `start..end` of the `len`-byte chunk,
+    /// widened to whole verification groups. `nodes` are the chunk's BLAKE3
+    /// tree nodes covering that range, in pre-order.
+    Slice {
+        hash: [u8; 32],
+        len: u64,
+        start: u64,
+        end: u64,
+        nodes: Vec<([u8; 32], [u8; 32])>,
+    },
     Bye,
 }
@@ -0,0 +1,129 @@
+use crate::bitfield::Bitfield;
+use crate::chunk::Chunk;
+use crate::error::{Result, SyncError};
+use crate::manifest::{self, AnyManifest, Manifest, PublisherPolicy};
+use crate::net::download::{Downloader, complete_manifest};
+use crate::net::protocol::Message;
+use crate::net::quic::{self, Peer};
+use crate::net::server::ServeConfig;
+use crate::storage;
+
+/// Outcome of a push, as reported by the receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushReport {
+    /// Chunks pulled from the pusher.
+    pub fetched: u64,
+    /// Chunks the receiver already held.
+    pub present: u64,
+}
+
+/// Offers `manifest` to `peer` and waits until it has pulled the chunks it
+/// lacks. The caller must be serving its chunk store and manifests on the
+/// same connection (see [`crate::net::server::handle_peer`]) so the receiver
+/// can reach them: the offer is sent compact and the receiver fetches the
+/// chunk lists with `GetHashes`.
+pub async fn push(peer: &Peer, manifest: &AnyManifest) -> Result<PushReport> {
+    let data = match manifest {
+        AnyManifest::File(m) => serde_json::to_vec(&m.compact())?,
+        AnyManifest::Directory(d) => serde_json::to_vec(&d.compact())?,
+    };
+    let (mut send, mut recv) = peer.connection.open_bi().await?;
+    quic::send_message(&mut send, &Message::Push { manifest: data }).await?;
+    match quic::receive_message(&mut recv).await? {
+        Message::Pushed { fetched, present } => Ok(PushReport { fetched, present }),
+        Message::Refused { reason } => Err(SyncError::Other(format!("push refused: {reason}"))),
+        _ => Err(SyncError::Other("peer does not accept pushes".into())),
+    }
+}
+
+/// Handles a `Push` from `peer`: fills in the chunk lists of the offered
+/// manifest and pulls the chunks that are not in the store yet back over
+/// the same connection, verifying each, then records the manifest so it is
+/// served from now on.
+pub async fn receive_push(
+    peer: &Peer,
+    config: &ServeConfig,
+    manifest_json: &[u8],
+) -> Result<PushReport> {
+    if !config.accepts_push_from(&peer.id) {
+        return Err(SyncError::Other(format!(
+            "node {} may not push here",
+            peer.id
+        )));
+    }
+    let mut offered: AnyManifest = serde_json::from_slice(manifest_json)?;
+    let files: Vec<&mut Manifest> = match &mut offered {
+        AnyManifest::File(m) => vec![m],
+        AnyManifest::Directory(d) => {
+            d.validate()?;
+            d.check_paths()?;
+            if !manifest::is_safe_relative(&d.name) {
+                return Err(SyncError::Other(format!("invalid share name {:?}", d.name)));
+            }
+            d.files.iter_mut().map(|f| &mut f.manifest).collect()
+        }
+    };
+    let pusher = [peer.clone()];
+    for m in files {
+        complete_manifest(&pusher, m).await?;
+        check_offered(m)?;
+    }
+    let (files, dir) = match &offered {
+        AnyManifest::File(m) => (vec![m], None),
+        AnyManifest::Directory(d) => (d.files.iter().map(|f| &f.manifest).collect(), Some(d)),
+    };
+
+    let mut report = PushReport {
+        fetched: 0,
+        present: 0,
+    };
+    for m in files {
+        let share = m.share_id();
+        let mut have = Bitfield::new(m.chunks.len() as u64);
+        for (index, hash) in m.chunks.iter().enumerate() {
+            if storage::has_object(&config.storage_dir, hash) {
+                have.set(index as u64);
+                report.present += 1;
+            }
+        }
+        Downloader::new(vec![peer.clone()], share.clone(), m)
+            .completed(have)
+            .run(|index, data| {
+                let chunk = Chunk {
+                    index,
+                    hash: m.chunks[index as usize],
+                    size: data.len(),
+                    data,
+                };
+                storage::save_object(&config.storage_dir, &chunk)?;
+                report.fetched += 1;
+                Ok(())
+            })
+            .await?;
+        manifest::write_manifest(m, &manifest::manifest_path(&config.manifest_dir, &share))?;
+    }
+    if let Some(d) = dir {
+        let path = manifest::directory_manifest_path(&config.manifest_dir, &d.name);
+        manifest::write_directory_manifest(d, &path)?;
+    }
+    Ok(report)
+}
+
+/// Rejects manifests that could not be served as they are.
+fn check_offered(m: &Manifest) -> Result<()> {
+    if !manifest::is_safe_relative(&m.share_id()) {
+        return Err(SyncError::Other(format!(
+            "invalid share name {:?}",
+            m.share_id()
+        )));
+    }
+    if m.root.is_none() {
+        return Err(SyncError::Other(format!(
+            "manifest of {} has no content ID",
+            m.file_name
+        )));
+    }
+    m.verify_root()?;
+    PublisherPolicy::default().check(m)?;
+    Ok(())
+}
@@ -1,91 +1,292 @@
 use crate::crypto;
-use crate::crypto::NodeKeypair;
+use crate::crypto::{NodeId, NodeKeypair};
 use crate::error::{Result, SyncError};
+use crate::merkle;
 use crate::net::protocol::Message;
 use quinn::rustls::client::danger::{
     HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
 };
-use quinn::rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
-use quinn::{ClientConfig, Endpoint, ServerConfig};
+use quinn::rustls::crypto::WebPkiSupportedAlgorithms;
+use quinn::rustls::server::ParsedCertificate;
+use quinn::rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use quinn::rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme};
+use quinn::{ClientConfig, Endpoint, Incoming, ServerConfig};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::fmt;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024; // 16 MB
 
 const ALPN_PROTOCOL: &[u8] = b"p2rent/1";
 
-/// pubkey (32) + timestamp (8) + ed25519 signature (64)
-const HANDSHAKE_SIZE: usize = 32 + 8 + 64;
-const MAX_HANDSHAKE_DRIFT_SECS: u64 = 60;
+/// Handshake roles, signed into every proof so one end's proof can never be
+/// reflected back as the other's. Both the same length.
+pub const CLIENT_ROLE: &[u8] = b"P2RENT-HANDSHAKE-CLIENT";
+pub const SERVER_ROLE: &[u8] = b"P2RENT-HANDSHAKE-SERVER";
+
+/// TLS exporter label for the handshake's session binding.
+const EXPORTER_LABEL: &[u8] = b"EXPORTER-p2rent-handshake";
+
+const NONCE_SIZE: usize = 32;
+pub const PROOF_SIZE: usize = 64;
 
 #[derive(Debug, Clone)]
 pub struct Peer {
+    /// Node proven by the certificate the other end presented in the TLS
+    /// handshake.
     pub id: crypto::NodeId,
     pub connection: quinn::Connection,
 }
 
-fn generate_self_signed_cert() -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
-    let certified_key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
-    let cert_der = CertificateDer::from(certified_key.cert.der().to_vec());
-    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
-        certified_key.signing_key.serialize_der(),
-    ));
-    Ok((cert_der, key_der))
+/// DER of an Ed25519 PKCS#8 private key (RFC 8410) up to the 32-byte seed.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// DER of an Ed25519 SubjectPublicKeyInfo (RFC 8410) up to the 32-byte key.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Self-signed certificate for the node's own Ed25519 key, so completing the
+/// TLS handshake proves the node id.
+fn node_certificate(
+    keypair: &NodeKeypair,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let mut der = ED25519_PKCS8_PREFIX.to_vec();
+    der.extend_from_slice(&keypair.signing.to_bytes());
+    let key_der = PrivatePkcs8KeyDer::from(der);
+    let signing_key = rcgen::KeyPair::from_pkcs8_der_and_sign_algo(&key_der, &rcgen::PKCS_ED25519)?;
+    let cert =
+        rcgen::CertificateParams::new(vec!["localhost".to_string()])?.self_signed(&signing_key)?;
+    Ok((cert.der().clone(), PrivateKeyDer::Pkcs8(key_der)))
 }
 
-fn current_unix_secs() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+/// Ed25519 node key a certificate was issued to.
+fn cert_public_key(cert: &CertificateDer<'_>) -> Result<[u8; 32]> {
+    let spki = ParsedCertificate::try_from(cert)?.subject_public_key_info();
+    spki.as_ref()
+        .strip_prefix(&ED25519_SPKI_PREFIX[..])
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| {
+            SyncError::Other("peer certificate does not carry an Ed25519 node key".into())
+        })
 }
 
-fn build_handshake_bytes(keypair: &NodeKeypair) -> Result<Vec<u8>> {
-    let node_id = crypto::node_id(keypair);
-    let now = current_unix_secs();
-    let payload = crypto::build_handshake_payload(&node_id, now);
-    let signature = crypto::sign(keypair, &payload)?;
+/// Node id of the Ed25519 key a certificate was issued to.
+pub fn cert_node_id(cert: &CertificateDer<'_>) -> Result<NodeId> {
+    Ok(crypto::node_id_from_pubkey(&cert_public_key(cert)?))
+}
 
-    let mut out = Vec::with_capacity(HANDSHAKE_SIZE);
-    out.extend_from_slice(&keypair.verifying.to_bytes());
-    out.extend_from_slice(&now.to_be_bytes());
-    out.extend_from_slice(&signature);
-    Ok(out)
+/// Node key of the certificate the other end presented during the TLS
+/// handshake of `conn`.
+pub fn connection_public_key(conn: &quinn::Connection) -> Result<[u8; 32]> {
+    let certs = conn
+        .peer_identity()
+        .and_then(|id| id.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .ok_or_else(|| SyncError::Other("peer presented no certificate".into()))?;
+    let cert = certs
+        .first()
+        .ok_or_else(|| SyncError::Other("peer presented no certificate".into()))?;
+    cert_public_key(cert)
 }
 
-fn verify_handshake(data: &[u8]) -> Result<crypto::NodeId> {
-    if data.len() != HANDSHAKE_SIZE {
-        return Err(SyncError::Other(format!(
-            "invalid handshake: expected {} bytes, got {}",
-            HANDSHAKE_SIZE,
-            data.len()
-        )));
+/// Checks a peer certificate against the node key it carries instead of a
+/// CA: the certificate must hold an Ed25519 key, the handshake must be signed
+/// with that key, and its node id must be `expected` when one is given.
+/// Servers use it to require a node certificate from every client too.
+#[derive(Debug)]
+struct NodeCertVerifier {
+    expected: Option<NodeId>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl NodeCertVerifier {
+    fn new(expected: Option<NodeId>) -> Self {
+        NodeCertVerifier {
+            expected,
+            algorithms: quinn::rustls::crypto::ring::default_provider()
+                .signature_verification_algorithms,
+        }
+    }
+
+    fn check(&self, cert: &CertificateDer<'_>) -> std::result::Result<(), quinn::rustls::Error> {
+        let id = cert_node_id(cert).map_err(|e| quinn::rustls::Error::General(e.to_string()))?;
+        match &self.expected {
+            Some(expected) if *expected != id => Err(quinn::rustls::Error::General(format!(
+                "peer is node {id}, expected {expected}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ServerCertVerifier for NodeCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &rustls_pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls_pki_types::UnixTime,
+    ) -> std::result::Result<ServerCertVerified, quinn::rustls::Error> {
+        self.check(end_entity)?;
+        Ok(ServerCertVerified::assertion())
     }
 
-    let pubkey: [u8; 32] = data[0..32].try_into().unwrap();
-    let timestamp = u64::from_be_bytes(data[32..40].try_into().unwrap());
-    let sig: [u8; 64] = data[40..104].try_into().unwrap();
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, quinn::rustls::Error> {
+        quinn::rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
 
-    let node_id = crypto::node_id_from_pubkey(&pubkey);
-    let payload = crypto::build_handshake_payload(&node_id, timestamp);
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, quinn::rustls::Error> {
+        quinn::rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
 
-    if !crypto::verify(&pubkey, &payload, &sig)? {
-        return Err(SyncError::Other(
-            "handshake signature verification failed".into(),
-        ));
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
     }
+}
 
-    let now = current_unix_secs();
-    let drift = now.abs_diff(timestamp);
-    if drift > MAX_HANDSHAKE_DRIFT_SECS {
-        return Err(SyncError::Other(format!(
-            "handshake timestamp drift {drift}s exceeds {MAX_HANDSHAKE_DRIFT_SECS}s limit"
-        )));
+impl ClientCertVerifier for NodeCertVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: rustls_pki_types::UnixTime,
+    ) -> std::result::Result<ClientCertVerified, quinn::rustls::Error> {
+        self.check(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, quinn::rustls::Error> {
+        quinn::rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, quinn::rustls::Error> {
+        quinn::rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+/// A peer to dial: `host:port`, or `<node id>@host:port` to insist on the
+/// node found there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerAddr {
+    pub node: Option<NodeId>,
+    pub addr: String,
+}
+
+impl PeerAddr {
+    pub fn parse(s: &str) -> Result<Self> {
+        let Some((node, addr)) = s.split_once('@') else {
+            return Ok(PeerAddr {
+                node: None,
+                addr: s.to_string(),
+            });
+        };
+        if merkle::parse_content_id(node).is_none() {
+            return Err(SyncError::Other(format!(
+                "invalid peer {s:?}: node id must be 64 hex digits"
+            )));
+        }
+        Ok(PeerAddr {
+            node: Some(node.to_lowercase()),
+            addr: addr.to_string(),
+        })
+    }
+
+    pub async fn resolve(&self) -> Result<SocketAddr> {
+        tokio::net::lookup_host(self.addr.as_str())
+            .await?
+            .next()
+            .ok_or_else(|| SyncError::Other(format!("could not resolve {}", self.addr)))
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.node {
+            Some(node) => write!(f, "{node}@{}", self.addr),
+            None => f.write_str(&self.addr),
+        }
     }
+}
 
-    Ok(node_id)
+impl FromStr for PeerAddr {
+    type Err = SyncError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PeerAddr::parse(s)
+    }
+}
+
+/// Keying material exported from the TLS session of `conn`. Both ends
+/// derive the same value and no other session shares it, so a proof signed
+/// over it is useless on any other connection.
+pub fn session_secret(conn: &quinn::Connection) -> Result<[u8; 32]> {
+    let mut secret = [0u8; 32];
+    conn.export_keying_material(&mut secret, EXPORTER_LABEL, &[])
+        .map_err(|_| SyncError::Other("TLS session exports no keying material".into()))?;
+    Ok(secret)
+}
+
+/// Signature proving that `keypair` holds this session, answering the other
+/// end's `challenge`.
+pub fn handshake_proof(
+    keypair: &NodeKeypair,
+    role: &[u8],
+    session: &[u8; 32],
+    challenge: &[u8; 32],
+) -> Result<[u8; PROOF_SIZE]> {
+    let payload = crypto::build_handshake_payload(role, session, challenge);
+    Ok(crypto::sign(keypair, &payload)?.as_slice().try_into()?)
+}
+
+/// Checks a proof from [`handshake_proof`] against the key the other end's
+/// certificate carries.
+pub fn verify_handshake_proof(
+    pubkey: &[u8; 32],
+    role: &[u8],
+    session: &[u8; 32],
+    challenge: &[u8; 32],
+    proof: &[u8; PROOF_SIZE],
+) -> Result<()> {
+    let payload = crypto::build_handshake_payload(role, session, challenge);
+    if !crypto::verify(pubkey, &payload, proof)? {
+        return Err(SyncError::Other(
+            "handshake signature verification failed".into(),
+        ));
+    }
+    Ok(())
 }
 
 pub struct QuicServer {
@@ -95,9 +296,9 @@ pub struct QuicServer {
 
 impl QuicServer {
     pub async fn bind(addr: SocketAddr, keypair: NodeKeypair) -> Result<Self> {
-        let (cert, key) = generate_self_signed_cert()?;
+        let (cert, key) = node_certificate(&keypair)?;
         let mut server_crypto = quinn::rustls::ServerConfig::builder()
-            .with_no_client_auth()
+            .with_client_cert_verifier(Arc::new(NodeCertVerifier::new(None)))
             .with_single_cert(vec![cert], key)?;
         server_crypto.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
         let quic_crypto = quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)
@@ -107,23 +308,50 @@ impl QuicServer {
         Ok(Self { endpoint, keypair })
     }
 
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Accepts the next client. Clients must present a certificate issued to
+    /// their node key, which `Peer::id` is taken from.
     pub async fn accept_and_handshake(&self) -> Result<Peer> {
-        let incoming = self
-            .endpoint
+        let incoming = self.accept().await?;
+        self.handshake(incoming).await
+    }
+
+    /// Waits for the next connection attempt without handshaking it, so a
+    /// slow client can be handshaken alongside others.
+    pub async fn accept(&self) -> Result<Incoming> {
+        self.endpoint
             .accept()
             .await
-            .ok_or_else(|| SyncError::Other("endpoint closed".into()))?;
+            .ok_or_else(|| SyncError::Other("endpoint closed".into()))
+    }
+
+    /// Completes the TLS and node key handshake of a connection attempt from
+    /// [`QuicServer::accept`].
+    pub async fn handshake(&self, incoming: Incoming) -> Result<Peer> {
         let conn = incoming.await?;
+        let client_key = connection_public_key(&conn)?;
+        let session = session_secret(&conn)?;
         let (mut send, mut recv) = conn.accept_bi().await?;
 
-        let client_hello = receive_raw(&mut recv, HANDSHAKE_SIZE).await?;
-        let client_id = verify_handshake(&client_hello)?;
+        // The client challenges first; answer it and challenge back.
+        let mut client_nonce = [0u8; NONCE_SIZE];
+        recv.read_exact(&mut client_nonce).await?;
+        let nonce = crypto::random_nonce()?;
+        let proof = handshake_proof(&self.keypair, SERVER_ROLE, &session, &client_nonce)?;
+        send.write_all(&nonce).await?;
+        send.write_all(&proof).await?;
 
-        let server_hello = build_handshake_bytes(&self.keypair)?;
-        send_raw(&mut send, &server_hello).await?;
+        let mut client_proof = [0u8; PROOF_SIZE];
+        recv.read_exact(&mut client_proof).await?;
+        verify_handshake_proof(&client_key, CLIENT_ROLE, &session, &nonce, &client_proof)?;
+        // Finishing tells the client its proof was accepted.
+        send.finish()?;
 
         Ok(Peer {
-            id: client_id,
+            id: crypto::node_id_from_pubkey(&client_key),
             connection: conn,
         })
     }
@@ -135,84 +363,105 @@ pub struct QuicClient {
 
 impl QuicClient {
     pub async fn new() -> Result<Self> {
-        #[derive(Debug)]
-        struct SkipServerVerification;
-
-        impl ServerCertVerifier for SkipServerVerification {
-            fn verify_server_cert(
-                &self,
-                _end_entity: &CertificateDer<'_>,
-                _intermediates: &[CertificateDer<'_>],
-                _server_name: &rustls_pki_types::ServerName<'_>,
-                _ocsp_response: &[u8],
-                _now: rustls_pki_types::UnixTime,
-            ) -> std::result::Result<ServerCertVerified, quinn::rustls::Error> {
-                Ok(ServerCertVerified::assertion())
-            }
-
-            fn verify_tls12_signature(
-                &self,
-                _message: &[u8],
-                _cert: &CertificateDer<'_>,
-                _dss: &DigitallySignedStruct,
-            ) -> std::result::Result<HandshakeSignatureValid, quinn::rustls::Error> {
-                Ok(HandshakeSignatureValid::assertion())
-            }
-
-            fn verify_tls13_signature(
-                &self,
-                _message: &[u8],
-                _cert: &CertificateDer<'_>,
-                _dss: &DigitallySignedStruct,
-            ) -> std::result::Result<HandshakeSignatureValid, quinn::rustls::Error> {
-                Ok(HandshakeSignatureValid::assertion())
-            }
-
-            fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-                vec![
-                    SignatureScheme::ECDSA_NISTP256_SHA256,
-                    SignatureScheme::ED25519,
-                    SignatureScheme::RSA_PSS_SHA256,
-                    SignatureScheme::RSA_PKCS1_SHA256,
-                ]
-            }
-        }
+        let endpoint = Endpoint::client("0.0.0.0:0".parse().unwrap())?;
+        Ok(Self { endpoint })
+    }
 
+    fn client_config(keypair: &NodeKeypair, expected: Option<NodeId>) -> Result<ClientConfig> {
+        let (cert, key) = node_certificate(keypair)?;
         let roots = RootCertStore::empty();
         let mut rustls_config = quinn::rustls::ClientConfig::builder()
             .with_root_certificates(roots)
-            .with_no_client_auth();
+            .with_client_auth_cert(vec![cert], key)?;
         rustls_config
             .dangerous()
-            .set_certificate_verifier(Arc::new(SkipServerVerification));
+            .set_certificate_verifier(Arc::new(NodeCertVerifier::new(expected)));
         rustls_config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
 
         let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(rustls_config)
             .map_err(|e| SyncError::Other(format!("QUIC client crypto config: {e}")))?;
-        let client_config = ClientConfig::new(Arc::new(crypto));
+        Ok(ClientConfig::new(Arc::new(crypto)))
+    }
 
-        let mut endpoint = Endpoint::client("0.0.0.0:0".parse().unwrap())?;
-        endpoint.set_default_client_config(client_config);
+    /// Connects to whichever node answers at `addr`; `Peer::id` is the node
+    /// its TLS certificate proves.
+    pub async fn connect_and_handshake(
+        &self,
+        addr: SocketAddr,
+        keypair: &NodeKeypair,
+    ) -> Result<Peer> {
+        self.connect(addr, None, keypair).await
+    }
 
-        Ok(Self { endpoint })
+    /// Connects to `addr`, failing the TLS handshake unless the server
+    /// proves it is `node`.
+    pub async fn connect_to_node(
+        &self,
+        addr: SocketAddr,
+        node: &NodeId,
+        keypair: &NodeKeypair,
+    ) -> Result<Peer> {
+        self.connect(addr, Some(node.clone()), keypair).await
     }
 
-    pub async fn connect_and_handshake(
+    /// Resolves `peer` and connects, pinning its node id if it names one.
+    pub async fn connect_peer(&self, peer: &PeerAddr, keypair: &NodeKeypair) -> Result<Peer> {
+        let addr = peer.resolve().await?;
+        self.connect(addr, peer.node.clone(), keypair).await
+    }
+
+    /// Completes only the TLS handshake with whichever node answers at
+    /// `addr`, leaving the challenge-response to the caller. Nothing is
+    /// proven about the server until that has been done.
+    pub async fn connect_tls(
+        &self,
+        addr: SocketAddr,
+        keypair: &NodeKeypair,
+    ) -> Result<quinn::Connection> {
+        self.tls_connect(addr, None, keypair).await
+    }
+
+    async fn tls_connect(
         &self,
         addr: SocketAddr,
+        expected: Option<NodeId>,
+        keypair: &NodeKeypair,
+    ) -> Result<quinn::Connection> {
+        let config = Self::client_config(keypair, expected)?;
+        Ok(self
+            .endpoint
+            .connect_with(config, addr, "localhost")?
+            .await?)
+    }
+
+    async fn connect(
+        &self,
+        addr: SocketAddr,
+        expected: Option<NodeId>,
         keypair: &NodeKeypair,
     ) -> Result<Peer> {
-        let conn = self.endpoint.connect(addr, "localhost")?.await?;
+        let conn = self.tls_connect(addr, expected, keypair).await?;
+        let server_key = connection_public_key(&conn)?;
+        let session = session_secret(&conn)?;
         let (mut send, mut recv) = conn.open_bi().await?;
 
-        let client_hello = build_handshake_bytes(keypair)?;
-        send_raw(&mut send, &client_hello).await?;
+        let nonce = crypto::random_nonce()?;
+        send.write_all(&nonce).await?;
 
-        let server_hello = receive_raw(&mut recv, HANDSHAKE_SIZE).await?;
-        let server_id = verify_handshake(&server_hello)?;
+        let mut server_nonce = [0u8; NONCE_SIZE];
+        let mut server_proof = [0u8; PROOF_SIZE];
+        recv.read_exact(&mut server_nonce).await?;
+        recv.read_exact(&mut server_proof).await?;
+        verify_handshake_proof(&server_key, SERVER_ROLE, &session, &nonce, &server_proof)?;
+
+        let proof = handshake_proof(keypair, CLIENT_ROLE, &session, &server_nonce)?;
+        send.write_all(&proof).await?;
+        send.finish()?;
+        // The server finishes its side once it has checked our proof.
+        recv.read_to_end(0).await?;
 
         Ok(Peer {
-            id: server_id,
+            id: crypto::node_id_from_pubkey(&server_key),
             connection: conn,
         })
     }
@@ -241,3 +490,29 @@ pub async fn receive_message(stream: &mut quinn::RecvStream) -> Result<Message>
         .map_err(|e| SyncError::Other(format!("bincode decode: {e}")))?;
     Ok(msg)
 }
+
+/// Sends a length-prefixed message and leaves the stream open, so raw data
+/// can follow it.
+pub async fn send_header(stream: &mut quinn::SendStream, msg: &Message) -> Result<()> {
+    let data =
+        bincode::serialize(msg).map_err(|e| SyncError::Other(format!("bincode encode: {e}")))?;
+    stream.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    stream.write_all(&data).await?;
+    Ok(())
+}
+
+/// Reads a message sent with [`send_header`], leaving the rest of the stream
+/// unread.
+pub async fn receive_header(stream: &mut quinn::RecvStream) -> Result<Message> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(SyncError::Other(format!(
+            "message of {len} bytes is too large"
+        )));
+    }
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data).await?;
+    bincode::deserialize(&data).map_err(|e| SyncError::Other(format!("bincode decode: {e}")))
+}
@@ -0,0 +1,496 @@
+use crate::bitfield::Bitfield;
+use crate::chunk::Chunking;
+use crate::crypto::NodeId;
+use crate::error::{Result, SyncError};
+use crate::manifest::{self, AnyManifest, Manifest};
+use crate::merkle::{self, MerkleTree};
+use crate::net::protocol::{
+    MAX_HASHES_PER_REPLY, MAX_SHARES_PER_REPLY, ManifestQuery, Message, ShareInfo,
+};
+use crate::net::push;
+use crate::net::quic::{self, Peer, QuicServer};
+use crate::outboard;
+use crate::peers::AuthorizedPeers;
+use crate::storage;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Where `serve` finds the content it hands out.
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    pub storage_dir: PathBuf,
+    pub manifest_dir: PathBuf,
+    /// Node ids allowed to push content into this store.
+    push_from: Vec<NodeId>,
+    /// Nodes allowed to connect at all; anyone when unset.
+    authorized: Option<Arc<Mutex<AuthorizedPeers>>>,
+    /// Files already looked up by content ID.
+    by_root: Arc<Mutex<HashMap<[u8; 32], Arc<Indexed>>>>,
+}
+
+/// A served file found by content ID, with its tree ready for proofs.
+#[derive(Debug)]
+struct Indexed {
+    manifest: Manifest,
+    leaves: Vec<([u8; 32], u64)>,
+    tree: MerkleTree,
+}
+
+impl ServeConfig {
+    pub fn new(storage_dir: PathBuf, manifest_dir: PathBuf) -> Self {
+        ServeConfig {
+            storage_dir,
+            manifest_dir,
+            push_from: Vec::new(),
+            authorized: None,
+            by_root: Arc::default(),
+        }
+    }
+
+    /// Accepts pushes from these nodes; nobody may push by default.
+    pub fn accept_push_from(mut self, ids: Vec<NodeId>) -> Self {
+        self.push_from = ids;
+        self
+    }
+
+    pub fn accepts_push_from(&self, id: &str) -> bool {
+        self.push_from.iter().any(|p| p == id)
+    }
+
+    /// Lets only the nodes in `peers` connect.
+    pub fn authorized_peers(mut self, peers: AuthorizedPeers) -> Self {
+        self.authorized = Some(Arc::new(Mutex::new(peers)));
+        self
+    }
+
+    /// Checks that node `id` may connect, picking up any edit to the
+    /// authorized peers file first. Returns the label of its entry.
+    pub fn authorize(&self, id: &str) -> Result<Option<String>> {
+        let Some(authorized) = &self.authorized else {
+            return Ok(None);
+        };
+        let mut peers = authorized.lock().unwrap();
+        if let Err(e) = peers.reload_if_changed() {
+            return Err(SyncError::Other(format!(
+                "cannot read authorized peers: {e}"
+            )));
+        }
+        match peers.get(id) {
+            Some(peer) => Ok(peer.label.clone()),
+            None => Err(SyncError::Other(format!(
+                "node {id} is not listed in {}",
+                peers.path().display()
+            ))),
+        }
+    }
+
+    /// The manifest shared under `share`, if there is one. A content ID in
+    /// hex also names the file it identifies.
+    fn manifest(&self, share: &str) -> Option<Manifest> {
+        if !is_share_id(share) {
+            return None;
+        }
+        manifest::read_manifest(&manifest::manifest_path(&self.manifest_dir, share))
+            .ok()
+            .or_else(|| {
+                let root = merkle::parse_content_id(share)?;
+                Some(self.by_root(&root)?.manifest.clone())
+            })
+    }
+
+    /// JSON of the manifest `query` names: a file by content ID or share id,
+    /// or else a directory by its name. File manifests, including those in a
+    /// directory, go out compact: chunk lists would not fit one message for
+    /// large files and are served in proven batches by `GetHashes` instead.
+    fn manifest_data(&self, query: &ManifestQuery) -> Option<Vec<u8>> {
+        match query {
+            ManifestQuery::Root(root) => {
+                serde_json::to_vec(&self.by_root(root)?.manifest.compact()).ok()
+            }
+            ManifestQuery::Share(share) if !is_share_id(share) => None,
+            ManifestQuery::Share(share) => match self.manifest(share) {
+                Some(m) => serde_json::to_vec(&m.compact()).ok(),
+                None => {
+                    let d = manifest::read_any_manifest(&manifest::directory_manifest_path(
+                        &self.manifest_dir,
+                        share,
+                    ))
+                    .ok()?;
+                    match d {
+                        AnyManifest::Directory(d) => serde_json::to_vec(&d.compact()).ok(),
+                        AnyManifest::File(_) => None,
+                    }
+                }
+            },
+        }
+    }
+
+    /// The file with content ID `root`. Manifests are only scanned again on
+    /// a miss, and those that record a different ID are skipped unhashed.
+    fn by_root(&self, root: &[u8; 32]) -> Option<Arc<Indexed>> {
+        if let Some(hit) = self.by_root.lock().unwrap().get(root) {
+            return Some(hit.clone());
+        }
+        for path in manifest::list_manifests(&self.manifest_dir).ok()? {
+            let Ok(m) = manifest::read_manifest(&path) else {
+                continue;
+            };
+            let recorded = m.root.as_deref().and_then(merkle::parse_content_id);
+            if m.is_compact() || recorded.is_some_and(|r| r != *root) {
+                continue;
+            }
+            let leaves = m.merkle_leaves();
+            let tree = MerkleTree::new(leaves.iter().copied());
+            if merkle::content_id(m.file_size, tree.len(), &tree.root()) != *root {
+                continue;
+            }
+            let indexed = Arc::new(Indexed {
+                manifest: m,
+                leaves,
+                tree,
+            });
+            self.by_root.lock().unwrap().insert(*root, indexed.clone());
+            return Some(indexed);
+        }
+        None
+    }
+
+    fn hashes(&self, root: [u8; 32], start: u64, count: u64) -> Option<Message> {
+        let ix = self.by_root(&root)?;
+        let total = ix.tree.len();
+        if start > total || (start == total && total > 0) {
+            return None;
+        }
+        let end = start
+            .saturating_add(count.min(MAX_HASHES_PER_REPLY))
+            .min(total);
+        let m = &ix.manifest;
+        Some(Message::Hashes {
+            root,
+            tree_root: ix.tree.root(),
+            file_size: m.file_size,
+            total,
+            chunk_size: m.chunk_size as u64,
+            cdc: match m.chunking {
+                Chunking::Fixed => None,
+                Chunking::Cdc { min, avg, max } => Some([min, avg, max]),
+            },
+            start,
+            leaves: ix.leaves[start as usize..end as usize].to_vec(),
+            proof: ix.tree.prove(start, end),
+        })
+    }
+
+    /// Header and data of a `Slice` reply for bytes `start..end` of a stored
+    /// chunk.
+    fn slice(&self, hash: [u8; 32], start: u64, end: u64) -> Result<(Message, Vec<u8>)> {
+        let len = storage::object_len(&self.storage_dir, &hash)?;
+        let ob = storage::load_outboard(&self.storage_dir, &hash)?;
+        let nodes = outboard::slice_nodes(&ob, len, start, end)?;
+        let (start, end) = outboard::group_range(len, start, end);
+        let data = storage::read_object_range(&self.storage_dir, &hash, start, end)?;
+        let header = Message::Slice {
+            hash,
+            len,
+            start,
+            end,
+            nodes,
+        };
+        Ok((header, data))
+    }
+
+    /// Every file and directory manifest in `manifest_dir`, by share id.
+    /// Unreadable manifests are left out.
+    fn catalog(&self) -> Vec<ShareInfo> {
+        let share_of = |path: &PathBuf, suffix: &str| {
+            path.strip_prefix(&self.manifest_dir)
+                .ok()?
+                .to_str()?
+                .strip_suffix(suffix)
+                .map(str::to_string)
+        };
+        let mut shares = Vec::new();
+        for path in manifest::list_manifests(&self.manifest_dir).unwrap_or_default() {
+            let (Some(share), Ok(m)) = (
+                share_of(&path, ".manifest.json"),
+                manifest::read_manifest(&path),
+            ) else {
+                continue;
+            };
+            shares.push(ShareInfo {
+                share,
+                directory: false,
+                size: m.file_size,
+                chunks: m.chunks.len() as u64,
+                root: m.root.as_deref().and_then(merkle::parse_content_id),
+                publisher: m.verify_signature().ok().flatten(),
+            });
+        }
+        for path in manifest::list_directory_manifests(&self.manifest_dir).unwrap_or_default() {
+            let (Some(share), Ok(AnyManifest::Directory(d))) = (
+                share_of(&path, ".dir.json"),
+                manifest::read_any_manifest(&path),
+            ) else {
+                continue;
+            };
+            let mut publishers = d
+                .files
+                .iter()
+                .map(|f| f.manifest.verify_signature().ok().flatten());
+            let first = publishers.next().flatten();
+            let publisher = first.filter(|p| publishers.all(|q| q.as_ref() == Some(p)));
+            shares.push(ShareInfo {
+                share,
+                directory: true,
+                size: d.total_size(),
+                chunks: d.files.iter().map(|f| f.manifest.chunks.len() as u64).sum(),
+                root: None,
+                publisher,
+            });
+        }
+        shares.sort_by(|a, b| a.share.cmp(&b.share));
+        shares
+    }
+
+    /// A page of the catalog. Listing from the start takes a fresh snapshot
+    /// for `session`; later pages come from that snapshot rather than from
+    /// re-reading every manifest.
+    fn shares(&self, session: &Session, start: u64, count: u64) -> Message {
+        let catalog = {
+            let mut snapshot = session.catalog.lock().unwrap();
+            match &*snapshot {
+                Some(catalog) if start > 0 => catalog.clone(),
+                _ => snapshot.insert(Arc::new(self.catalog())).clone(),
+            }
+        };
+        let total = catalog.len() as u64;
+        let start = start.min(total);
+        let end = start
+            .saturating_add(count.min(MAX_SHARES_PER_REPLY))
+            .min(total);
+        Message::Shares {
+            total,
+            start,
+            shares: catalog[start as usize..end as usize].to_vec(),
+        }
+    }
+
+    /// Chunk directory of the older per-stem layout.
+    fn legacy_dir(&self, share: &str) -> Option<PathBuf> {
+        is_share_id(share).then(|| self.storage_dir.join(share))
+    }
+
+    fn load_indexed(&self, share: &str, index: u64) -> Result<Vec<u8>> {
+        if let Some(m) = self.manifest(share) {
+            let hash = m
+                .chunks
+                .get(index as usize)
+                .ok_or_else(|| SyncError::Other(format!("{share} has no chunk {index}")))?;
+            return storage::load_object(&self.storage_dir, hash);
+        }
+        let dir = self
+            .legacy_dir(share)
+            .ok_or_else(|| SyncError::Other(format!("invalid share name {share:?}")))?;
+        let dir_str = dir.to_str().unwrap_or(".");
+        Ok(storage::load_chunk(dir_str, index)?.data)
+    }
+
+    fn availability(&self, share: &str, need: &Bitfield) -> Bitfield {
+        let mut have = Bitfield::new(need.len());
+        if let Some(m) = self.manifest(share) {
+            for index in need.iter_ones() {
+                if m.chunks
+                    .get(index as usize)
+                    .is_some_and(|h| storage::has_object(&self.storage_dir, h))
+                {
+                    have.set(index);
+                }
+            }
+        } else if let Some(dir) = self.legacy_dir(share) {
+            let dir_str = dir.to_str().unwrap_or(".");
+            for index in need.iter_ones() {
+                if storage::has_chunk(dir_str, index) {
+                    have.set(index);
+                }
+            }
+        }
+        have
+    }
+}
+
+/// Share ids from the wire are relative paths and must not walk out of the
+/// served directories.
+fn is_share_id(id: &str) -> bool {
+    manifest::is_safe_relative(id)
+}
+
+/// How long a connecting client gets to finish its handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts peers for as long as the server runs, handing each one that is
+/// authorized to [`handle_peer`] and closing the connection of any other.
+/// Every connection is handshaken in its own task, so a client that stalls
+/// mid-handshake holds up no one else.
+pub async fn serve_forever(server: QuicServer, config: Arc<ServeConfig>) {
+    let server = Arc::new(server);
+    loop {
+        let incoming = match server.accept().await {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                eprintln!("Failed to accept peer: {}", e);
+                return;
+            }
+        };
+        let (server, config) = (server.clone(), config.clone());
+        tokio::spawn(async move {
+            let peer = match timeout(HANDSHAKE_TIMEOUT, server.handshake(incoming)).await {
+                Ok(Ok(peer)) => peer,
+                Ok(Err(e)) => return eprintln!("Failed to accept peer: {}", e),
+                Err(_) => return eprintln!("Failed to accept peer: handshake timed out"),
+            };
+            match config.authorize(&peer.id) {
+                Ok(label) => {
+                    match label {
+                        Some(label) => println!("Accepted new peer: {} ({label})", peer.id),
+                        None => println!("Accepted new peer: {}", peer.id),
+                    }
+                    handle_peer(peer, config).await;
+                }
+                Err(e) => {
+                    eprintln!("Refused peer {}: {e}", peer.id);
+                    peer.connection.close(1u32.into(), b"not authorized");
+                }
+            }
+        });
+    }
+}
+
+/// State kept for one connection while it is served.
+struct Session {
+    peer: Peer,
+    /// Catalog as of the connection's last listing from the start.
+    catalog: Mutex<Option<Arc<Vec<ShareInfo>>>>,
+}
+
+/// Serves requests from `peer` until it disconnects. Authorization is
+/// checked again for every request, so a node taken off the authorized
+/// peers file also loses a connection it already holds.
+pub async fn handle_peer(peer: Peer, config: Arc<ServeConfig>) {
+    println!("Handling connection with {}", peer.id);
+    let session = Arc::new(Session {
+        peer,
+        catalog: Mutex::new(None),
+    });
+    // Each request arrives on its own stream; serve them concurrently so a
+    // client's in-flight window is not serialized here.
+    while let Ok((send, recv)) = session.peer.connection.accept_bi().await {
+        if let Err(e) = config.authorize(&session.peer.id) {
+            eprintln!("Dropped peer {}: {e}", session.peer.id);
+            session
+                .peer
+                .connection
+                .close(1u32.into(), b"not authorized");
+            return;
+        }
+        tokio::spawn(handle_stream(send, recv, config.clone(), session.clone()));
+    }
+}
+
+async fn handle_stream(
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+    config: Arc<ServeConfig>,
+    session: Arc<Session>,
+) {
+    let peer = &session.peer;
+    match quic::receive_message(&mut recv).await {
+        Ok(Message::RequestChunk { share, index }) => match config.load_indexed(&share, index) {
+            Ok(data) => {
+                let msg = Message::Chunk { index, data };
+                let _ = quic::send_message(&mut send, &msg).await;
+            }
+            Err(e) => {
+                let _ = quic::send_message(&mut send, &Message::Bye).await;
+                eprintln!("load_chunk error: {}", e);
+            }
+        },
+        Ok(Message::RequestChunkByHash { hash }) => {
+            match storage::load_object(&config.storage_dir, &hash) {
+                Ok(data) => {
+                    let msg = Message::ChunkByHash { hash, data };
+                    let _ = quic::send_message(&mut send, &msg).await;
+                }
+                Err(e) => {
+                    let _ = quic::send_message(&mut send, &Message::Bye).await;
+                    eprintln!("load_object error: {}", e);
+                }
+            }
+        }
+        Ok(Message::RequestSlice { hash, start, end }) => match config.slice(hash, start, end) {
+            Ok((header, data)) => {
+                if quic::send_header(&mut send, &header).await.is_ok()
+                    && send.write_all(&data).await.is_ok()
+                {
+                    let _ = send.finish();
+                }
+            }
+            Err(e) => {
+                let _ = quic::send_header(&mut send, &Message::Bye).await;
+                let _ = send.finish();
+                eprintln!("slice error: {}", e);
+            }
+        },
+        Ok(Message::GetManifest { query }) => {
+            let msg = config
+                .manifest_data(&query)
+                .map_or(Message::Bye, |manifest| Message::ManifestData { manifest });
+            let _ = quic::send_message(&mut send, &msg).await;
+        }
+        Ok(Message::Push { manifest }) => {
+            let msg = match push::receive_push(peer, &config, &manifest).await {
+                Ok(report) => {
+                    println!(
+                        "Push from {}: {} chunk(s) received, {} already stored",
+                        peer.id, report.fetched, report.present
+                    );
+                    Message::Pushed {
+                        fetched: report.fetched,
+                        present: report.present,
+                    }
+                }
+                Err(e) => {
+                    eprintln!("push from {} refused: {}", peer.id, e);
+                    Message::Refused {
+                        reason: e.to_string(),
+                    }
+                }
+            };
+            let _ = quic::send_message(&mut send, &msg).await;
+        }
+        Ok(Message::ListShares { start, count }) => {
+            let msg = config.shares(&session, start, count);
+            let _ = quic::send_message(&mut send, &msg).await;
+        }
+        Ok(Message::GetHashes { root, start, count }) => {
+            let msg = config.hashes(root, start, count).unwrap_or(Message::Bye);
+            let _ = quic::send_message(&mut send, &msg).await;
+        }
+        Ok(Message::Need { share, chunks }) if chunks.is_well_formed() => {
+            let have = config.availability(&share, &chunks);
+            let msg = Message::Have {
+                share,
+                chunks: have,
+            };
+            let _ = quic::send_message(&mut send, &msg).await;
+        }
+        Ok(_) => {
+            let _ = quic::send_message(&mut send, &Message::Bye).await;
+        }
+        Err(e) => {
+            eprintln!("recv error: {}", e);
+        }
+    }
+}
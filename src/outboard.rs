@@ -0,0 +1,229 @@
+use crate::error::{Result, SyncError};
+use blake3::hazmat::{
+    ChainingValue, HasherExt, Mode, left_subtree_len, merge_subtrees_non_root, merge_subtrees_root,
+};
+use std::collections::VecDeque;
+
+/// Bytes in one verification group, the smallest unit a slice is checked in.
+/// A power-of-two number of BLAKE3 chunks, so every group is a whole subtree
+/// of the chunk's own BLAKE3 tree and its digest stays the root.
+pub const GROUP_LEN: u64 = 4096;
+
+/// Size of one stored parent node: its left and right chaining values.
+pub const NODE_LEN: usize = 64;
+
+/// Left and right chaining values of a parent node in a chunk's BLAKE3 tree.
+pub type Node = ([u8; 32], [u8; 32]);
+
+/// Parent nodes above group level in the tree of a `len`-byte chunk.
+fn parents(len: u64) -> u64 {
+    len.div_ceil(GROUP_LEN).saturating_sub(1)
+}
+
+/// Length of the outboard of a `len`-byte chunk.
+pub fn outboard_len(len: u64) -> u64 {
+    parents(len) * NODE_LEN as u64
+}
+
+fn group_cv(data: &[u8], offset: u64) -> ChainingValue {
+    let mut h = blake3::Hasher::new();
+    h.set_input_offset(offset);
+    h.update(data);
+    h.finalize_non_root()
+}
+
+/// BLAKE3 outboard of `data`: every parent node above group level, in
+/// pre-order, [`NODE_LEN`] bytes each. Empty when `data` fits in one group.
+pub fn outboard(data: &[u8]) -> Vec<u8> {
+    let mut nodes = Vec::new();
+    if data.len() as u64 > GROUP_LEN {
+        build(data, 0, &mut nodes);
+    }
+    nodes.concat()
+}
+
+fn build(data: &[u8], offset: u64, nodes: &mut Vec<[u8; NODE_LEN]>) -> ChainingValue {
+    let len = data.len() as u64;
+    if len <= GROUP_LEN {
+        return group_cv(data, offset);
+    }
+    let at = nodes.len();
+    nodes.push([0; NODE_LEN]);
+    let split = left_subtree_len(len);
+    let (left, right) = data.split_at(split as usize);
+    let l = build(left, offset, nodes);
+    let r = build(right, offset + split, nodes);
+    nodes[at][..32].copy_from_slice(&l);
+    nodes[at][32..].copy_from_slice(&r);
+    merge_subtrees_non_root(&l, &r, Mode::Hash)
+}
+
+fn overlaps(offset: u64, len: u64, start: u64, end: u64) -> bool {
+    offset < end && start < offset + len
+}
+
+fn check_range(len: u64, start: u64, end: u64) -> Result<()> {
+    if start >= end || end > len {
+        return Err(SyncError::Other(format!(
+            "range {start}..{end} is not inside a {len}-byte chunk"
+        )));
+    }
+    Ok(())
+}
+
+/// Bytes `start..end` widened to whole groups: what a slice reply carries.
+pub fn group_range(len: u64, start: u64, end: u64) -> (u64, u64) {
+    (
+        start / GROUP_LEN * GROUP_LEN,
+        end.div_ceil(GROUP_LEN).saturating_mul(GROUP_LEN).min(len),
+    )
+}
+
+/// The nodes a [`SliceVerifier`] needs for bytes `start..end` of a
+/// `len`-byte chunk, picked out of the chunk's outboard.
+pub fn slice_nodes(outboard: &[u8], len: u64, start: u64, end: u64) -> Result<Vec<Node>> {
+    check_range(len, start, end)?;
+    if outboard.len() as u64 != outboard_len(len) {
+        return Err(SyncError::Other(
+            "outboard does not match the chunk length".into(),
+        ));
+    }
+    let mut nodes = Vec::new();
+    if len > GROUP_LEN {
+        select(outboard, 0, len, start, end, &mut 0, &mut nodes);
+    }
+    Ok(nodes)
+}
+
+fn select(
+    outboard: &[u8],
+    offset: u64,
+    len: u64,
+    start: u64,
+    end: u64,
+    next: &mut u64,
+    nodes: &mut Vec<Node>,
+) {
+    let at = *next as usize * NODE_LEN;
+    let node = &outboard[at..at + NODE_LEN];
+    nodes.push((
+        node[..32].try_into().unwrap(),
+        node[32..].try_into().unwrap(),
+    ));
+    *next += 1;
+    let split = left_subtree_len(len);
+    for (o, l) in [(offset, split), (offset + split, len - split)] {
+        if l > GROUP_LEN && overlaps(o, l, start, end) {
+            select(outboard, o, l, start, end, next, nodes);
+        } else {
+            *next += parents(l);
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expected {
+    /// The whole chunk is one group, checked against its digest.
+    Root([u8; 32]),
+    Cv(ChainingValue),
+}
+
+/// Checks a slice of a chunk against the chunk's digest one group at a time,
+/// so a bad sender is caught after at most [`GROUP_LEN`] bytes.
+#[derive(Debug)]
+pub struct SliceVerifier {
+    /// Groups still to come: offset, length and what they must hash to.
+    groups: VecDeque<(u64, u64, Expected)>,
+}
+
+impl SliceVerifier {
+    /// Verifies `nodes` (from [`slice_nodes`]) top-down against `hash`, the
+    /// digest of a `len`-byte chunk, leaving the groups covering
+    /// `start..end` to be checked as their bytes arrive.
+    pub fn new(hash: &[u8; 32], len: u64, start: u64, end: u64, nodes: &[Node]) -> Result<Self> {
+        check_range(len, start, end)?;
+        let mut groups = VecDeque::new();
+        let mut nodes = nodes.iter();
+        walk(
+            0,
+            len,
+            Expected::Root(*hash),
+            start,
+            end,
+            &mut nodes,
+            &mut groups,
+        )?;
+        if nodes.next().is_some() {
+            return Err(SyncError::Other("slice proof has extra nodes".into()));
+        }
+        Ok(SliceVerifier { groups })
+    }
+
+    /// Length of the next group to pass to [`SliceVerifier::verify`], or
+    /// `None` once the slice is complete.
+    pub fn next_len(&self) -> Option<usize> {
+        self.groups.front().map(|&(_, len, _)| len as usize)
+    }
+
+    /// Checks the next group of the slice.
+    pub fn verify(&mut self, data: &[u8]) -> Result<()> {
+        let Some((offset, len, expected)) = self.groups.pop_front() else {
+            return Err(SyncError::Other("slice is already complete".into()));
+        };
+        let ok = data.len() as u64 == len
+            && match expected {
+                Expected::Root(hash) => *blake3::hash(data).as_bytes() == hash,
+                Expected::Cv(cv) => group_cv(data, offset) == cv,
+            };
+        if !ok {
+            return Err(SyncError::Other(format!(
+                "slice bytes {offset}..{} do not match the chunk hash",
+                offset + len
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn walk<'a>(
+    offset: u64,
+    len: u64,
+    expected: Expected,
+    start: u64,
+    end: u64,
+    nodes: &mut impl Iterator<Item = &'a Node>,
+    groups: &mut VecDeque<(u64, u64, Expected)>,
+) -> Result<()> {
+    if len <= GROUP_LEN {
+        groups.push_back((offset, len, expected));
+        return Ok(());
+    }
+    let (l, r) = nodes
+        .next()
+        .ok_or_else(|| SyncError::Other("slice proof is missing nodes".into()))?;
+    let ok = match expected {
+        Expected::Root(hash) => merge_subtrees_root(l, r, Mode::Hash).as_bytes() == &hash,
+        Expected::Cv(cv) => merge_subtrees_non_root(l, r, Mode::Hash) == cv,
+    };
+    if !ok {
+        return Err(SyncError::Other(
+            "slice proof does not match the chunk hash".into(),
+        ));
+    }
+    let split = left_subtree_len(len);
+    if overlaps(offset, split, start, end) {
+        walk(offset, split, Expected::Cv(*l), start, end, nodes, groups)?;
+    }
+    if overlaps(offset + split, len - split, start, end) {
+        walk(
+            offset + split,
+            len - split,
+            Expected::Cv(*r),
+            start,
+            end,
+            nodes,
+            groups,
+        )?;
+    }
+    Ok(())
+}
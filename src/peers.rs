@@ -0,0 +1,251 @@
+use crate::crypto::{self, NodeId, NodeKeypair};
+use crate::error::{Result, SyncError};
+use crate::merkle;
+use crate::net::quic::{Peer, PeerAddr, QuicClient};
+use base64::{Engine as _, engine::general_purpose};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Modification time and length of a file when it was last read.
+type Stamp = (SystemTime, u64);
+
+/// A node allowed to connect, with the label its entry gave it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizedPeer {
+    pub id: NodeId,
+    pub label: Option<String>,
+}
+
+/// The nodes `serve` lets in, read from an `authorized_peers` file: one
+/// node id (64 hex digits) or base64 Ed25519 public key per line, optionally
+/// followed by a label. Blank lines and `#` comments are skipped.
+///
+/// The file is re-read whenever it changes on disk, so peers can be added or
+/// removed without restarting.
+#[derive(Debug)]
+pub struct AuthorizedPeers {
+    path: PathBuf,
+    stamp: Option<Stamp>,
+    peers: HashMap<NodeId, AuthorizedPeer>,
+}
+
+impl AuthorizedPeers {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut list = AuthorizedPeers {
+            path: path.to_path_buf(),
+            stamp: None,
+            peers: HashMap::new(),
+        };
+        list.reload_if_changed()?;
+        Ok(list)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Re-reads the file if it changed since it was last read; returns
+    /// whether it did. If it cannot be read or parsed the list is emptied,
+    /// so a broken file shuts everyone out rather than letting everyone in.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        match self.read_if_changed() {
+            Ok(None) => Ok(false),
+            Ok(Some((stamp, peers))) => {
+                self.stamp = Some(stamp);
+                self.peers = peers;
+                Ok(true)
+            }
+            Err(e) => {
+                self.stamp = None;
+                self.peers.clear();
+                Err(SyncError::Other(format!("{}: {e}", self.path.display())))
+            }
+        }
+    }
+
+    fn read_if_changed(&self) -> Result<Option<(Stamp, HashMap<NodeId, AuthorizedPeer>)>> {
+        let meta = fs::metadata(&self.path)?;
+        let stamp = (meta.modified()?, meta.len());
+        if self.stamp == Some(stamp) {
+            return Ok(None);
+        }
+        Ok(Some((stamp, parse(&fs::read_to_string(&self.path)?)?)))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&AuthorizedPeer> {
+        self.peers.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+fn parse(text: &str) -> Result<HashMap<NodeId, AuthorizedPeer>> {
+    let mut peers = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, label) = match line.split_once(char::is_whitespace) {
+            Some((key, label)) => (key, Some(label.trim().to_string())),
+            None => (line, None),
+        };
+        let id = parse_peer_key(key).ok_or_else(|| {
+            SyncError::Other(format!(
+                "line {}: {key:?} is neither a node id nor a base64 public key",
+                n + 1
+            ))
+        })?;
+        peers.insert(id.clone(), AuthorizedPeer { id, label });
+    }
+    Ok(peers)
+}
+
+/// Node id named by `key`: a node id itself, or the base64 Ed25519 public
+/// key it is derived from.
+pub fn parse_peer_key(key: &str) -> Option<NodeId> {
+    if merkle::parse_content_id(key).is_some() {
+        return Some(key.to_lowercase());
+    }
+    let bytes = general_purpose::STANDARD.decode(key).ok()?;
+    (bytes.len() == 32).then(|| crypto::node_id_from_pubkey(&bytes))
+}
+
+/// What a [`KnownPeers`] store says about the node found at an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerCheck {
+    /// Nothing was recorded for the address yet.
+    New,
+    /// The same node as before.
+    Known,
+    /// A different node than the one recorded.
+    Changed { previous: NodeId },
+}
+
+/// The node id last seen at each address, like SSH's `known_hosts`: the
+/// first connection to an address is trusted and recorded, and later ones
+/// are checked against it. One `<address> <node id>` pair per line.
+#[derive(Debug)]
+pub struct KnownPeers {
+    path: PathBuf,
+    peers: BTreeMap<String, NodeId>,
+}
+
+impl KnownPeers {
+    /// `known_peers` beside the node's key file.
+    pub fn default_path() -> PathBuf {
+        crypto::default_key_path().with_file_name("known_peers")
+    }
+
+    /// Reads the store at `path`; a missing file is an empty store.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut peers = BTreeMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line
+                .split_once(char::is_whitespace)
+                .map(|(addr, id)| (addr, id.trim()))
+                .filter(|(_, id)| merkle::parse_content_id(id).is_some());
+            let Some((addr, id)) = entry else {
+                return Err(SyncError::Other(format!(
+                    "{}: line {} is not `<address> <node id>`",
+                    path.display(),
+                    n + 1
+                )));
+            };
+            peers.insert(addr.to_string(), id.to_lowercase());
+        }
+        Ok(KnownPeers {
+            path: path.to_path_buf(),
+            peers,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, addr: &str) -> Option<&NodeId> {
+        self.peers.get(addr)
+    }
+
+    pub fn check(&self, addr: &str, id: &str) -> PeerCheck {
+        match self.peers.get(addr) {
+            None => PeerCheck::New,
+            Some(known) if known == id => PeerCheck::Known,
+            Some(known) => PeerCheck::Changed {
+                previous: known.clone(),
+            },
+        }
+    }
+
+    /// Records `id` for `addr`, returning the node it replaces.
+    pub fn insert(&mut self, addr: &str, id: &str) -> Option<NodeId> {
+        self.peers.insert(addr.to_string(), id.to_lowercase())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut text = String::new();
+        for (addr, id) in &self.peers {
+            text.push_str(&format!("{addr} {id}\n"));
+        }
+        let parent = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)?;
+        let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+        tmp.write_all(text.as_bytes())?;
+        tmp.persist(&self.path)
+            .map_err(|e| SyncError::Io(e.error))?;
+        Ok(())
+    }
+}
+
+/// Connects to `addr`, trusting the node first seen at an address and
+/// refusing a different one later; a pinned `<node id>@host:port` is checked
+/// against its pin instead. Returns the peer and whether it was newly
+/// recorded in `known`, which is saved when it is.
+pub async fn connect_known(
+    client: &QuicClient,
+    addr: &PeerAddr,
+    keypair: &NodeKeypair,
+    known: &mut KnownPeers,
+) -> Result<(Peer, bool)> {
+    let peer = client.connect_peer(addr, keypair).await?;
+    if addr.node.is_some() {
+        return Ok((peer, false));
+    }
+    match known.check(&addr.addr, &peer.id) {
+        PeerCheck::Known => Ok((peer, false)),
+        PeerCheck::New => {
+            known.insert(&addr.addr, &peer.id);
+            known.save()?;
+            Ok((peer, true))
+        }
+        PeerCheck::Changed { previous } => {
+            peer.connection.close(0u32.into(), b"unknown node");
+            Err(SyncError::Other(format!(
+                "{} was node {previous} but is now node {}; if that is expected, run `p2rent accept-peer {}`",
+                addr.addr, peer.id, addr.addr
+            )))
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::bitfield::Bitfield;
+use crate::error::Result;
+use crate::manifest::Manifest;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Progress of an interrupted fetch: which manifest it was for and which
+/// chunks were already verified and written into the `.part` file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResumeState {
+    pub manifest_hash: String,
+    pub completed: Bitfield,
+}
+
+impl ResumeState {
+    pub fn new(manifest: &Manifest) -> Self {
+        ResumeState {
+            manifest_hash: manifest.digest(),
+            completed: Bitfield::new(manifest.chunks.len() as u64),
+        }
+    }
+
+    /// Whether this record belongs to `manifest`.
+    pub fn matches(&self, manifest: &Manifest) -> bool {
+        self.manifest_hash == manifest.digest()
+            && self.completed.is_well_formed()
+            && self.completed.len() == manifest.chunks.len() as u64
+    }
+}
+
+/// Resume record kept next to the output file while a fetch is incomplete.
+pub fn resume_path(out: &Path) -> PathBuf {
+    let mut name = out.as_os_str().to_owned();
+    name.push(".p2rent-resume.json");
+    PathBuf::from(name)
+}
+
+pub fn load_resume(path: &Path) -> Result<Option<ResumeState>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(Some(serde_json::from_str(&data)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn save_resume(state: &ResumeState, path: &Path) -> Result<()> {
+    let json = serde_json::to_string(state)?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
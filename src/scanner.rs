@@ -14,3 +14,17 @@ pub fn scan_directory(dir: &Path) -> Result<Vec<PathBuf>> {
 
     Ok(files)
 }
+
+/// Directories below `dir` that have no entries at all.
+pub fn scan_empty_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+
+    for entry in WalkDir::new(dir).min_depth(1) {
+        let entry = entry?;
+        if entry.file_type().is_dir() && std::fs::read_dir(entry.path())?.next().is_none() {
+            dirs.push(entry.path().to_path_buf());
+        }
+    }
+
+    Ok(dirs)
+}
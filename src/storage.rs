@@ -1,7 +1,8 @@
 use crate::chunk::Chunk;
 use crate::error::{Result, SyncError};
+use crate::outboard;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 fn chunk_path(dir: &Path, index: u64) -> PathBuf {
@@ -19,6 +20,10 @@ pub fn save_chunk(dir: &str, chunk: &Chunk) -> Result<()> {
     Ok(())
 }
 
+pub fn has_chunk(dir: &str, index: u64) -> bool {
+    chunk_path(Path::new(dir), index).is_file()
+}
+
 pub fn load_chunk(dir: &str, index: u64) -> Result<Chunk> {
     let dir_path = Path::new(dir);
     let path = chunk_path(dir_path, index);
@@ -33,3 +38,139 @@ pub fn load_chunk(dir: &str, index: u64) -> Result<Chunk> {
         data,
     })
 }
+
+/// Path of the content-addressed object for `hash`:
+/// `<storage_dir>/objects/<first two hex digits>/<hex digest>`.
+pub fn object_path(storage_dir: &Path, hash: &[u8; 32]) -> PathBuf {
+    let hex = blake3::Hash::from_bytes(*hash).to_hex();
+    let mut path = storage_dir.join("objects");
+    path.push(&hex[..2]);
+    path.push(hex.as_str());
+    path
+}
+
+/// Path of the BLAKE3 outboard stored next to the object for `hash`.
+pub fn outboard_path(storage_dir: &Path, hash: &[u8; 32]) -> PathBuf {
+    object_path(storage_dir, hash).with_extension("obao")
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| SyncError::Other("object path has no parent".into()))?;
+    fs::create_dir_all(parent)?;
+    let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+    tmp.write_all(data)?;
+    tmp.persist(path).map_err(|e| SyncError::Io(e.error))?;
+    Ok(())
+}
+
+/// Stores a chunk under its BLAKE3 digest, with its outboard when it spans
+/// more than one verification group. Returns `false` when an object with
+/// that digest already exists, so identical chunks are only kept once.
+pub fn save_object(storage_dir: &Path, chunk: &Chunk) -> Result<bool> {
+    let path = object_path(storage_dir, &chunk.hash);
+    if path.is_file() {
+        return Ok(false);
+    }
+    let ob = outboard::outboard(&chunk.data);
+    if !ob.is_empty() {
+        write_atomic(&outboard_path(storage_dir, &chunk.hash), &ob)?;
+    }
+    write_atomic(&path, &chunk.data)?;
+    Ok(true)
+}
+
+pub fn has_object(storage_dir: &Path, hash: &[u8; 32]) -> bool {
+    object_path(storage_dir, hash).is_file()
+}
+
+/// Loads an object, rejecting it if its content no longer matches the digest.
+pub fn load_object(storage_dir: &Path, hash: &[u8; 32]) -> Result<Vec<u8>> {
+    let data = fs::read(object_path(storage_dir, hash))?;
+    if <[u8; 32]>::from(blake3::hash(&data)) != *hash {
+        return Err(SyncError::Other(format!(
+            "object {} is corrupt",
+            blake3::Hash::from_bytes(*hash).to_hex()
+        )));
+    }
+    Ok(data)
+}
+
+/// Length of the stored object for `hash`.
+pub fn object_len(storage_dir: &Path, hash: &[u8; 32]) -> Result<u64> {
+    Ok(fs::metadata(object_path(storage_dir, hash))?.len())
+}
+
+/// Loads the outboard of an object. Objects stored without one (or with a
+/// damaged one) are verified in full and get it rebuilt.
+pub fn load_outboard(storage_dir: &Path, hash: &[u8; 32]) -> Result<Vec<u8>> {
+    let len = object_len(storage_dir, hash)?;
+    let expected = outboard::outboard_len(len);
+    if expected == 0 {
+        return Ok(Vec::new());
+    }
+    let path = outboard_path(storage_dir, hash);
+    if let Ok(ob) = fs::read(&path)
+        && ob.len() as u64 == expected
+    {
+        return Ok(ob);
+    }
+    let ob = outboard::outboard(&load_object(storage_dir, hash)?);
+    write_atomic(&path, &ob)?;
+    Ok(ob)
+}
+
+/// Reads bytes `start..end` of an object without checking them; the reader
+/// verifies them against the outboard.
+pub fn read_object_range(
+    storage_dir: &Path,
+    hash: &[u8; 32],
+    start: u64,
+    end: u64,
+) -> Result<Vec<u8>> {
+    let mut f = File::open(object_path(storage_dir, hash))?;
+    f.seek(SeekFrom::Start(start))?;
+    let mut data = vec![0; end.saturating_sub(start) as usize];
+    f.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Advisory lock on a chunk store. `serve`, `share` and seeding fetches hold
+/// it shared for as long as they may read or add objects; garbage collection
+/// needs it exclusively.
+pub struct StoreLock {
+    _file: File,
+}
+
+impl StoreLock {
+    fn open(storage_dir: &Path) -> Result<File> {
+        fs::create_dir_all(storage_dir)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(storage_dir.join(".lock"))?;
+        Ok(file)
+    }
+
+    /// Waits for any running garbage collection to finish.
+    pub fn shared(storage_dir: &Path) -> Result<Self> {
+        let file = Self::open(storage_dir)?;
+        file.lock_shared()?;
+        Ok(StoreLock { _file: file })
+    }
+
+    /// Fails straight away if anything else is using the store.
+    pub fn exclusive(storage_dir: &Path) -> Result<Self> {
+        let file = Self::open(storage_dir)?;
+        file.try_lock().map_err(|e| match e {
+            fs::TryLockError::WouldBlock => SyncError::Other(format!(
+                "chunk store {} is in use (is serve running?)",
+                storage_dir.display()
+            )),
+            fs::TryLockError::Error(e) => SyncError::Io(e),
+        })?;
+        Ok(StoreLock { _file: file })
+    }
+}
@@ -1,46 +1,115 @@
 use crate::{
-    chunk::{self, Chunk},
+    chunk::{Chunk, ChunkReader, Chunking},
+    crypto::NodeKeypair,
     error::{Result, SyncError},
-    manifest::{self, Manifest},
-    scanner,
+    manifest::{self, DirectoryManifest, Manifest},
+    scanner, storage,
 };
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
-pub fn sync_directory(
-    root: &Path,
-    manifest_dir: &Path,
-    chunk_size: usize,
-) -> Result<Vec<Manifest>> {
-    fs::create_dir_all(manifest_dir).map_err(SyncError::Io)?;
+/// How shared files are chunked, stored and signed.
+pub struct ShareOptions<'a> {
+    pub chunk_size: usize,
+    pub chunking: Chunking,
+    pub storage_dir: &'a Path,
+    pub manifest_dir: &'a Path,
+    /// Publisher key the manifests are signed with, if any.
+    pub keypair: Option<&'a NodeKeypair>,
+    /// Overwrite manifests already recorded for different content.
+    pub replace: bool,
+}
 
-    let files = scanner::scan_directory(root)?;
-    let mut manifests = Vec::new();
+impl<'a> ShareOptions<'a> {
+    /// Fixed-size chunks of `chunk_size` bytes, unsigned.
+    pub fn new(chunk_size: usize, storage_dir: &'a Path, manifest_dir: &'a Path) -> Self {
+        ShareOptions {
+            chunk_size,
+            chunking: Chunking::Fixed,
+            storage_dir,
+            manifest_dir,
+            keypair: None,
+            replace: false,
+        }
+    }
 
-    for file in files {
-        let chunks: Vec<Chunk> = chunk::split_file(&file, chunk_size)
-            .map_err(|e| SyncError::Other(format!("Failed to chunk file {:?}: {}", file, e)))?;
+    pub fn with_chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
 
-        let file_name = file
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown")
-            .to_string();
+    pub fn signed_by(mut self, keypair: &'a NodeKeypair) -> Self {
+        self.keypair = Some(keypair);
+        self
+    }
 
-        let stem = file
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or(&file_name);
+    pub fn replace(mut self, replace: bool) -> Self {
+        self.replace = replace;
+        self
+    }
+}
 
-        let manifest = Manifest::from_chunks(file_name.clone(), chunk_size, &chunks);
+/// Shares `file`, found while sharing `root`, under its relative path: each
+/// chunk is stored and dropped before the next is read, then the manifest is
+/// signed and written. `on_chunk` sees every chunk and whether the store
+/// lacked it.
+///
+/// A single file is shared under its bare file name, so sharing another
+/// file of the same name fails unless `options.replace` is set rather than
+/// silently pointing the share at new content.
+pub fn share_file<F>(
+    file: &Path,
+    root: &Path,
+    options: &ShareOptions,
+    mut on_chunk: F,
+) -> Result<Manifest>
+where
+    F: FnMut(&Chunk, bool),
+{
+    let file_name = file
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown")
+        .to_string();
+    let mut manifest = Manifest::new(file_name, options.chunk_size).with_chunking(options.chunking);
+    manifest.share = manifest::share_id_for(root, file);
+    for chunk in ChunkReader::open_with(file, options.chunk_size, options.chunking)? {
+        let chunk = chunk
+            .map_err(|e| SyncError::Other(format!("Failed to chunk file {:?}: {}", file, e)))?;
+        let new = storage::save_object(options.storage_dir, &chunk)?;
+        manifest.push_chunk(&chunk);
+        on_chunk(&chunk, new);
+    }
+    manifest.set_root();
+    if let Some(keypair) = options.keypair {
+        manifest.sign(keypair)?;
+    }
 
-        let mut out_path = PathBuf::from(manifest_dir);
-        out_path.push(format!("{}.manifest.json", stem));
+    let out_path = manifest::manifest_path(options.manifest_dir, &manifest.share);
+    if !options.replace {
+        manifest::check_replace(&out_path, &manifest)?;
+    }
+    manifest::write_manifest(&manifest, &out_path)?;
+    Ok(manifest)
+}
 
-        manifest::write_manifest(&manifest, &out_path)?;
+/// Shares every file under `root` with [`share_file`] and writes the
+/// directory manifest tying them together.
+pub fn sync_directory(root: &Path, options: &ShareOptions) -> Result<DirectoryManifest> {
+    fs::create_dir_all(options.manifest_dir).map_err(SyncError::Io)?;
 
-        manifests.push(manifest);
+    let files = scanner::scan_directory(root)?;
+    let mut manifests = Vec::with_capacity(files.len());
+    for file in files {
+        let manifest = share_file(&file, root, options, |_, _| {})?;
+        manifests.push((file, manifest));
     }
 
-    Ok(manifests)
+    let dir_manifest = DirectoryManifest::new(root, manifests)?;
+    let out_path = manifest::directory_manifest_path(options.manifest_dir, &dir_manifest.name);
+    if !options.replace {
+        manifest::check_replace_directory(&out_path, &dir_manifest)?;
+    }
+    manifest::write_directory_manifest(&dir_manifest, &out_path)?;
+    Ok(dir_manifest)
 }
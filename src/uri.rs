@@ -0,0 +1,137 @@
+use crate::crypto::NodeId;
+use crate::error::{Result, SyncError};
+use crate::manifest::Manifest;
+use crate::merkle;
+use std::fmt;
+use std::str::FromStr;
+
+pub const SCHEME: &str = "p2rent://";
+
+/// A magnet-style link to a shared file:
+/// `p2rent://<content id>?name=<file name>&pub=<publisher node id>&peer=<host:port>`.
+///
+/// Only the content ID is required; it is what the fetched manifest and
+/// data are verified against. The rest are hints: a default output name,
+/// the publisher whose signature the manifest must carry, and peers to ask.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareUri {
+    pub root: [u8; 32],
+    pub name: Option<String>,
+    pub publisher: Option<NodeId>,
+    pub peers: Vec<String>,
+}
+
+impl ShareUri {
+    /// Link to the file described by `manifest`, naming its publisher when
+    /// the manifest is signed.
+    pub fn for_manifest(manifest: &Manifest, peers: Vec<String>) -> Result<Self> {
+        let root = manifest
+            .root
+            .as_deref()
+            .and_then(merkle::parse_content_id)
+            .ok_or_else(|| SyncError::Other("manifest has no content ID".into()))?;
+        Ok(ShareUri {
+            root,
+            name: Some(manifest.file_name.clone()),
+            publisher: manifest.verify_signature()?,
+            peers,
+        })
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let bad = |why: &str| SyncError::Other(format!("invalid p2rent URI {s:?}: {why}"));
+        let rest = s
+            .get(..SCHEME.len())
+            .filter(|p| p.eq_ignore_ascii_case(SCHEME))
+            .map(|_| &s[SCHEME.len()..])
+            .ok_or_else(|| bad("must start with p2rent://"))?;
+        let (id, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let root = merkle::parse_content_id(id.trim_end_matches('/'))
+            .ok_or_else(|| bad("content ID must be 64 hex digits"))?;
+        let mut uri = ShareUri {
+            root,
+            name: None,
+            publisher: None,
+            peers: Vec::new(),
+        };
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value).ok_or_else(|| bad("bad percent-encoding"))?;
+            match key {
+                "name" => {
+                    if !is_plain_file_name(&value) {
+                        return Err(bad("name must be a plain file name"));
+                    }
+                    uri.name = Some(value);
+                }
+                "pub" => {
+                    if merkle::parse_content_id(&value).is_none() {
+                        return Err(bad("pub must be a 64-hex-digit node id"));
+                    }
+                    uri.publisher = Some(value.to_lowercase());
+                }
+                "peer" => uri.peers.push(value),
+                // Unknown hints are skipped so newer links still work.
+                _ => {}
+            }
+        }
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for ShareUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{SCHEME}{}", merkle::content_id_hex(&self.root))?;
+        let params = self
+            .name
+            .iter()
+            .map(|n| ("name", n))
+            .chain(self.publisher.iter().map(|p| ("pub", p)))
+            .chain(self.peers.iter().map(|p| ("peer", p)));
+        for (i, (key, value)) in params.enumerate() {
+            let sep = if i == 0 { '?' } else { '&' };
+            write!(f, "{sep}{key}={}", percent_encode(value))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ShareUri {
+    type Err = SyncError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ShareUri::parse(s)
+    }
+}
+
+/// Whether `name` can be used as an output file name as it is: a single
+/// path component, not `.` or `..`.
+pub fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~:".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    String::from_utf8(out).ok()
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use base64::{Engine as _, engine::general_purpose};
+use common::{serve_config, spawn_server};
+use p2rent::crypto::{NodeKeypair, generate_keypair, node_id};
+use p2rent::net::download::list_shares;
+use p2rent::net::quic::{QuicClient, QuicServer};
+use p2rent::net::server::{ServeConfig, serve_forever};
+use p2rent::peers::AuthorizedPeers;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Whether `key` gets answers from the server at `addr`. A refused peer's
+/// connection is closed right after the handshake, which it may notice while
+/// still connecting.
+async fn served(client: &QuicClient, addr: SocketAddr, key: &NodeKeypair) -> bool {
+    match client.connect_and_handshake(addr, key).await {
+        Ok(peer) => list_shares(&peer).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[tokio::test]
+async fn only_listed_peers_are_served_and_edits_apply_live() {
+    let temp = tempfile::tempdir().unwrap();
+    let laptop = generate_keypair().unwrap();
+    let desktop = generate_keypair().unwrap();
+    let stranger = generate_keypair().unwrap();
+    let list = temp.path().join("authorized_peers");
+    let desktop_key = general_purpose::STANDARD.encode(desktop.verifying.to_bytes());
+    std::fs::write(
+        &list,
+        format!(
+            "# office seeds\n{} anna's laptop\n\n{desktop_key}\n",
+            node_id(&laptop).to_uppercase()
+        ),
+    )
+    .unwrap();
+
+    let peers = AuthorizedPeers::load(&list).unwrap();
+    assert_eq!(peers.len(), 2);
+    let entry = peers.get(&node_id(&laptop)).unwrap();
+    assert_eq!(entry.label.as_deref(), Some("anna's laptop"));
+    assert_eq!(peers.get(&node_id(&desktop)).unwrap().label, None);
+    assert!(peers.get(&node_id(&stranger)).is_none());
+
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let config = ServeConfig::new(temp.path().join("chunks"), temp.path().join("manifests"))
+        .authorized_peers(peers);
+    tokio::spawn(serve_forever(server, Arc::new(config)));
+    let client = QuicClient::new().await.unwrap();
+
+    assert!(served(&client, addr, &laptop).await);
+    assert!(served(&client, addr, &desktop).await);
+    assert!(!served(&client, addr, &stranger).await);
+
+    // Adding the stranger takes effect on its next connection.
+    let mut text = std::fs::read_to_string(&list).unwrap();
+    text.push_str(&format!("{} guest\n", node_id(&stranger)));
+    std::fs::write(&list, text).unwrap();
+    assert!(served(&client, addr, &stranger).await);
+
+    // A broken list lets nobody in.
+    std::fs::write(&list, "not-a-key\n").unwrap();
+    assert!(!served(&client, addr, &laptop).await);
+}
+
+#[tokio::test]
+async fn stalled_handshake_does_not_hold_up_other_peers() {
+    let temp = tempfile::tempdir().unwrap();
+    let addr = spawn_server(serve_config(temp.path())).await;
+    let client = QuicClient::new().await.unwrap();
+
+    // Completes TLS, then never starts the node key handshake.
+    let _idle = client
+        .connect_tls(addr, &generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let other = generate_keypair().unwrap();
+    let served = tokio::time::timeout(Duration::from_secs(5), served(&client, addr, &other)).await;
+    assert_eq!(served.ok(), Some(true));
+}
+
+#[tokio::test]
+async fn revoked_peer_loses_its_open_connection() {
+    let temp = tempfile::tempdir().unwrap();
+    let laptop = generate_keypair().unwrap();
+    let list = temp.path().join("authorized_peers");
+    std::fs::write(&list, format!("{} laptop\n", node_id(&laptop))).unwrap();
+    let config = serve_config(temp.path()).authorized_peers(AuthorizedPeers::load(&list).unwrap());
+    let addr = spawn_server(config).await;
+
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &laptop).await.unwrap();
+    assert!(list_shares(&peer).await.is_ok());
+
+    std::fs::write(&list, "# nobody\n").unwrap();
+    assert!(list_shares(&peer).await.is_err());
+}
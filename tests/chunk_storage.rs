@@ -1,4 +1,8 @@
-use p2rent::chunk::{Chunk, combine_chunks, split_file};
+mod common;
+
+use common::noise;
+use p2rent::chunk::{Chunk, ChunkReader, ChunkWriter, Chunking, combine_chunks, split_file};
+use p2rent::manifest::Manifest;
 use p2rent::storage;
 use std::fs;
 use std::io::Write;
@@ -35,3 +39,131 @@ fn storage_roundtrip() {
     let loaded = storage::load_chunk(dir.path().to_str().unwrap(), 0).unwrap();
     assert_eq!(loaded.data, b"hello");
 }
+
+#[test]
+fn chunk_reader_yields_full_chunks_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("stream.bin");
+    let data = (0..5000).map(|i| (i % 199) as u8).collect::<Vec<_>>();
+    fs::write(&file_path, &data).unwrap();
+
+    let chunks: Vec<Chunk> = ChunkReader::open(&file_path, 1000)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks.len(), 5);
+    for (i, c) in chunks.iter().enumerate() {
+        assert_eq!(c.index, i as u64);
+        assert_eq!(c.size, 1000);
+        assert_eq!(c.data, data[i * 1000..(i + 1) * 1000]);
+    }
+}
+
+#[test]
+fn chunk_writer_places_out_of_order_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src.bin");
+    let data = (0..2500).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write(&src, &data).unwrap();
+    let chunks = split_file(&src, 1000).unwrap();
+
+    let out = dir.path().join("out.bin");
+    let writer = ChunkWriter::create(&out, data.len() as u64, 1000).unwrap();
+    for c in chunks.iter().rev() {
+        writer.write_chunk(c.index, &c.data).unwrap();
+    }
+    assert!(writer.write_chunk(3, b"past the end").is_err());
+    assert_eq!(writer.read_chunk(2).unwrap(), chunks[2].data);
+    assert!(!out.exists());
+    writer.finish().unwrap();
+
+    assert_eq!(fs::read(&out).unwrap(), data);
+    assert!(!ChunkWriter::part_path(&out).exists());
+
+    assert!(ChunkWriter::create(&dir.path().join("zero.bin"), 10, 0).is_err());
+}
+
+#[test]
+fn object_store_deduplicates_by_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let chunk = |index| Chunk {
+        index,
+        hash: blake3::hash(b"same bytes").into(),
+        data: b"same bytes".to_vec(),
+        size: 10,
+    };
+    assert!(storage::save_object(dir.path(), &chunk(0)).unwrap());
+    assert!(!storage::save_object(dir.path(), &chunk(7)).unwrap());
+
+    let hash = chunk(0).hash;
+    assert_eq!(
+        storage::load_object(dir.path(), &hash).unwrap(),
+        b"same bytes"
+    );
+    fs::write(storage::object_path(dir.path(), &hash), b"tampered").unwrap();
+    assert!(storage::load_object(dir.path(), &hash).is_err());
+}
+
+#[test]
+fn cdc_chunks_survive_an_insert() {
+    let dir = tempfile::tempdir().unwrap();
+    let chunking = Chunking::cdc(4096, None, None).unwrap();
+    let original = noise(256 * 1024, 7);
+    let mut edited = original.clone();
+    edited.insert(1000, 0xaa);
+
+    let mut manifests = Vec::new();
+    for (name, data) in [("a.bin", &original), ("b.bin", &edited)] {
+        let path = dir.path().join(name);
+        fs::write(&path, data).unwrap();
+        let mut manifest = Manifest::new(name.into(), 4096).with_chunking(chunking);
+        for c in ChunkReader::open_with(&path, 4096, chunking).unwrap() {
+            let c = c.unwrap();
+            assert!(c.size <= 16384);
+            manifest.push_chunk(&c);
+        }
+        assert_eq!(manifest.file_size, data.len() as u64);
+        manifests.push(manifest);
+    }
+
+    let (a, b) = (&manifests[0], &manifests[1]);
+    let changed = b.chunks.iter().filter(|h| !a.chunks.contains(h)).count();
+    assert!(a.chunks.len() > 20);
+    assert!(
+        changed <= 2,
+        "{changed} of {} chunks changed",
+        b.chunks.len()
+    );
+    assert_eq!(b.chunk_range(b.chunks.len() - 1).unwrap().end, b.file_size);
+}
+
+#[test]
+fn cdc_manifest_drives_chunk_writer() {
+    let dir = tempfile::tempdir().unwrap();
+    let chunking = Chunking::cdc(1024, None, None).unwrap();
+    let data = noise(40_000, 3);
+    let path = dir.path().join("src.bin");
+    fs::write(&path, &data).unwrap();
+    let chunks: Vec<_> = ChunkReader::open_with(&path, 1024, chunking)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let mut manifest = Manifest::new("src.bin".into(), 1024).with_chunking(chunking);
+    for c in &chunks {
+        manifest.push_chunk(c);
+    }
+    assert_eq!(manifest.chunk_sizes.len(), chunks.len());
+
+    let out = dir.path().join("out.bin");
+    let writer = manifest.chunk_writer(&out).unwrap();
+    for c in chunks.iter().rev() {
+        writer.write_chunk(c.index, &c.data).unwrap();
+    }
+    writer.finish().unwrap();
+    assert_eq!(fs::read(&out).unwrap(), data);
+
+    let mut broken = manifest.clone();
+    broken.chunk_sizes.pop();
+    assert!(broken.chunk_writer(&out).is_err());
+    assert!(Chunking::cdc(4096, Some(8192), None).is_err());
+}
@@ -0,0 +1,53 @@
+#![allow(dead_code)]
+
+use p2rent::chunk::split_file;
+use p2rent::crypto::generate_keypair;
+use p2rent::manifest::{self, Manifest};
+use p2rent::net::quic::QuicServer;
+use p2rent::net::server::{ServeConfig, serve_forever};
+use p2rent::storage;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Serves `config` on a loopback port under a fresh node key.
+pub async fn spawn_server(config: ServeConfig) -> SocketAddr {
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(serve_forever(server, Arc::new(config)));
+    addr
+}
+
+/// What a node with its store under `dir` serves.
+pub fn serve_config(dir: &Path) -> ServeConfig {
+    ServeConfig::new(dir.join("chunks"), dir.join("manifests"))
+}
+
+/// Writes `data` to `dir/name`, chunks it into the object store under
+/// `dir/chunks` and writes its manifest under `dir/manifests`.
+pub fn share(dir: &Path, name: &str, data: &[u8], chunk_size: usize) -> Manifest {
+    let file_path = dir.join(name);
+    std::fs::write(&file_path, data).unwrap();
+    let chunks = split_file(&file_path, chunk_size).unwrap();
+    for c in &chunks {
+        storage::save_object(&dir.join("chunks"), c).unwrap();
+    }
+    let manifest = Manifest::from_chunks(name.into(), chunk_size, &chunks);
+    let path = manifest::manifest_path(&dir.join("manifests"), &manifest.share);
+    manifest::write_manifest(&manifest, &path).unwrap();
+    manifest
+}
+
+/// Deterministic pseudo-random bytes, so content-defined cut points vary.
+pub fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 56) as u8
+        })
+        .collect()
+}
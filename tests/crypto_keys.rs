@@ -1,9 +1,11 @@
-use p2rent::crypto::load_or_create_keypair;
+use p2rent::crypto::{generate_keypair, load_keypair, save_keypair};
 
 #[test]
-fn load_or_create_roundtrip() {
-    // Use default path; ensure it works twice
-    let kp1 = load_or_create_keypair().expect("create/load");
-    let kp2 = load_or_create_keypair().expect("load again");
+fn keypair_save_load_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("p2rent/node_key.json");
+    let kp1 = generate_keypair().unwrap();
+    save_keypair(&kp1, Some(&path)).expect("save");
+    let kp2 = load_keypair(Some(&path)).expect("load again");
     assert_eq!(kp1.verifying.to_bytes(), kp2.verifying.to_bytes());
 }
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{noise, spawn_server};
+use p2rent::bitfield::Bitfield;
+use p2rent::chunk::{ChunkReader, Chunking};
+use p2rent::crypto::generate_keypair;
+use p2rent::delta::reuse_local_chunks;
+use p2rent::manifest::{self, Manifest};
+use p2rent::net::download::Downloader;
+use p2rent::net::quic::QuicClient;
+use p2rent::net::server::ServeConfig;
+use p2rent::storage;
+
+#[tokio::test]
+async fn refetch_downloads_only_changed_chunks() {
+    let temp = tempfile::tempdir().unwrap();
+    let old = noise(200 * 1024, 11);
+    let mut new = old.clone();
+    new.splice(5000..5000, *b"inserted");
+    new.extend_from_slice(&noise(3000, 12));
+    let old_path = temp.path().join("old.bin");
+    let new_path = temp.path().join("data.bin");
+    std::fs::write(&old_path, &old).unwrap();
+    std::fs::write(&new_path, &new).unwrap();
+
+    // Publish the new version.
+    let chunking = Chunking::cdc(4096, None, None).unwrap();
+    let storage_dir = temp.path().join("chunks");
+    let manifest_dir = temp.path().join("manifests");
+    let mut manifest = Manifest::new("data.bin".into(), 4096).with_chunking(chunking);
+    for c in ChunkReader::open_with(&new_path, 4096, chunking).unwrap() {
+        let c = c.unwrap();
+        storage::save_object(&storage_dir, &c).unwrap();
+        manifest.push_chunk(&c);
+    }
+    let path = manifest::manifest_path(&manifest_dir, &manifest.share);
+    manifest::write_manifest(&manifest, &path).unwrap();
+
+    let keypair = generate_keypair().unwrap();
+    let addr = spawn_server(ServeConfig::new(storage_dir, manifest_dir)).await;
+
+    // Fill what the old copy already has, then download the rest.
+    let out = temp.path().join("out.bin");
+    let writer = manifest.chunk_writer(&out).unwrap();
+    let total = manifest.chunks.len() as u64;
+    let mut completed = Bitfield::new(total);
+    let reused =
+        reuse_local_chunks(&manifest, &old_path, &writer, &mut completed, |_| Ok(())).unwrap();
+    assert!(reused >= total - 4, "only {reused}/{total} chunks reused");
+    assert!(reused < total);
+
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+    let mut downloaded = 0;
+    Downloader::new(vec![peer], manifest.share.clone(), &manifest)
+        .completed(completed)
+        .run(|index, data| {
+            downloaded += 1;
+            writer.write_chunk(index, &data)
+        })
+        .await
+        .unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(downloaded, total - reused);
+    assert_eq!(std::fs::read(&out).unwrap(), new);
+}
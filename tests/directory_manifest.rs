@@ -0,0 +1,101 @@
+use p2rent::chunk::Chunking;
+use p2rent::crypto::{generate_keypair, node_id};
+use p2rent::manifest::{self, AnyManifest};
+use p2rent::storage;
+use p2rent::sync::{ShareOptions, sync_directory};
+use std::os::unix::fs::PermissionsExt;
+
+#[test]
+fn directory_manifest_records_tree_and_metadata() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("project");
+    std::fs::create_dir_all(root.join("src/bin")).unwrap();
+    std::fs::create_dir_all(root.join("empty/nested")).unwrap();
+    std::fs::write(root.join("README"), b"hello").unwrap();
+    std::fs::write(root.join("src/bin/run.sh"), b"#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(
+        root.join("src/bin/run.sh"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let manifest_dir = temp.path().join("manifests");
+    let storage_dir = temp.path().join("chunks");
+    sync_directory(&root, &ShareOptions::new(4, &storage_dir, &manifest_dir)).unwrap();
+
+    let path = manifest::directory_manifest_path(&manifest_dir, "project");
+    let AnyManifest::Directory(dir) = manifest::read_any_manifest(&path).unwrap() else {
+        panic!("expected a directory manifest");
+    };
+    dir.validate().unwrap();
+    assert_eq!(dir.name, "project");
+    assert_eq!(dir.empty_dirs, vec!["empty/nested".to_string()]);
+    let paths: Vec<_> = dir.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["README", "src/bin/run.sh"]);
+    let script = &dir.files[1];
+    assert_eq!(script.mode, 0o755);
+    assert_eq!(script.manifest.share, "project/src/bin/run.sh");
+    assert_eq!(script.manifest.file_size, 10);
+    assert_eq!(script.manifest.chunks.len(), 3);
+
+    // Per-file manifests still read as single-file manifests.
+    let file_path = manifest::manifest_path(&manifest_dir, "project/README");
+    assert!(matches!(
+        manifest::read_any_manifest(&file_path).unwrap(),
+        AnyManifest::File(_)
+    ));
+
+    // Read-only files can be restored again, and special bits from a
+    // manifest are never applied.
+    let copy = temp.path().join("copy");
+    std::fs::write(&copy, b"hello").unwrap();
+    let mut dir = dir;
+    let entry = &mut dir.files[0];
+    entry.mode = 0o4444;
+    entry.mtime = 1_000_000;
+    for _ in 0..2 {
+        entry.apply_metadata(&copy).unwrap();
+    }
+    let meta = std::fs::metadata(&copy).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o444);
+    assert_eq!(
+        meta.modified().unwrap(),
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000)
+    );
+
+    let mut hostile = dir;
+    hostile.files[0].path = "../escape".into();
+    assert!(hostile.validate().is_err());
+}
+
+#[test]
+fn directory_is_stored_chunked_and_signed_like_a_file_share() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("data");
+    std::fs::create_dir_all(root.join("sub")).unwrap();
+    std::fs::write(root.join("a.bin"), vec![1u8; 5000]).unwrap();
+    let mixed: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    std::fs::write(root.join("sub/b.bin"), mixed).unwrap();
+
+    let (storage_dir, manifest_dir) = (temp.path().join("chunks"), temp.path().join("manifests"));
+    let chunking = Chunking::cdc(1024, None, None).unwrap();
+    let publisher = generate_keypair().unwrap();
+    let options = ShareOptions::new(1024, &storage_dir, &manifest_dir)
+        .with_chunking(chunking)
+        .signed_by(&publisher);
+    let tree = sync_directory(&root, &options).unwrap();
+
+    assert_eq!(tree.files.len(), 2);
+    for entry in &tree.files {
+        let m = &entry.manifest;
+        assert_eq!(m.chunking, chunking);
+        assert_eq!(m.verify_signature().unwrap(), Some(node_id(&publisher)));
+        assert!(
+            m.chunks
+                .iter()
+                .all(|h| storage::has_object(&storage_dir, h))
+        );
+        let stored = manifest::read_manifest(&manifest::manifest_path(&manifest_dir, &m.share));
+        assert_eq!(stored.unwrap().signature, m.signature);
+    }
+}
@@ -0,0 +1,130 @@
+use p2rent::crypto::{NodeKeypair, generate_keypair, node_id, random_nonce};
+use p2rent::net::quic::{
+    CLIENT_ROLE, PROOF_SIZE, QuicClient, QuicServer, SERVER_ROLE, connection_public_key,
+    handshake_proof, session_secret, verify_handshake_proof,
+};
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+
+/// Runs the client side of the handshake by hand on a fresh TLS connection,
+/// answering with `proof` or, without one, with a genuine proof. Returns the
+/// proof sent and whether the server then accepted it by finishing its
+/// stream.
+async fn handshake_by_hand(
+    client: &QuicClient,
+    addr: SocketAddr,
+    key: &NodeKeypair,
+    proof: Option<[u8; PROOF_SIZE]>,
+) -> ([u8; PROOF_SIZE], bool) {
+    let conn = client.connect_tls(addr, key).await.unwrap();
+    let session = session_secret(&conn).unwrap();
+    let (mut send, mut recv) = conn.open_bi().await.unwrap();
+    send.write_all(&random_nonce().unwrap()).await.unwrap();
+    let mut server_nonce = [0u8; 32];
+    let mut server_proof = [0u8; PROOF_SIZE];
+    recv.read_exact(&mut server_nonce).await.unwrap();
+    recv.read_exact(&mut server_proof).await.unwrap();
+
+    let proof = match proof {
+        Some(proof) => proof,
+        None => handshake_proof(key, CLIENT_ROLE, &session, &server_nonce).unwrap(),
+    };
+    send.write_all(&proof).await.unwrap();
+    send.finish().unwrap();
+    (proof, recv.read_to_end(0).await.is_ok())
+}
+
+#[tokio::test]
+async fn captured_handshakes_do_not_verify_again() {
+    let server_key = generate_keypair().unwrap();
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), server_key.clone())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (tx, mut accepted) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(peer) = server.accept_and_handshake().await {
+            tx.send(peer).unwrap();
+        }
+    });
+
+    let client_key = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let first = client
+        .connect_and_handshake(addr, &client_key)
+        .await
+        .unwrap();
+    let second = client
+        .connect_and_handshake(addr, &client_key)
+        .await
+        .unwrap();
+    let first_seen = accepted.recv().await.unwrap();
+    assert_eq!(first.id, node_id(&server_key));
+    assert_eq!(first_seen.id, node_id(&client_key));
+
+    // Both ends of a connection share its session secret; no other
+    // connection does.
+    let session = session_secret(&first.connection).unwrap();
+    assert_eq!(session, session_secret(&first_seen.connection).unwrap());
+    let other_session = session_secret(&second.connection).unwrap();
+    assert_ne!(session, other_session);
+
+    let client_pub = connection_public_key(&first_seen.connection).unwrap();
+    assert_eq!(client_pub, client_key.verifying.to_bytes());
+
+    // A client proof captured from the first connection...
+    let challenge = random_nonce().unwrap();
+    let captured = handshake_proof(&client_key, CLIENT_ROLE, &session, &challenge).unwrap();
+    verify_handshake_proof(&client_pub, CLIENT_ROLE, &session, &challenge, &captured).unwrap();
+
+    // ...fails on another connection, even against the same challenge,
+    assert!(
+        verify_handshake_proof(
+            &client_pub,
+            CLIENT_ROLE,
+            &other_session,
+            &challenge,
+            &captured
+        )
+        .is_err()
+    );
+    // fails against the fresh challenge a server would send next,
+    let fresh = random_nonce().unwrap();
+    assert!(verify_handshake_proof(&client_pub, CLIENT_ROLE, &session, &fresh, &captured).is_err());
+    // cannot be reflected back as the server's answer,
+    assert!(
+        verify_handshake_proof(&client_pub, SERVER_ROLE, &session, &challenge, &captured).is_err()
+    );
+    // and proves nothing for a different key.
+    let other_pub = generate_keypair().unwrap().verifying.to_bytes();
+    assert!(
+        verify_handshake_proof(&other_pub, CLIENT_ROLE, &session, &challenge, &captured).is_err()
+    );
+}
+
+#[tokio::test]
+async fn server_rejects_a_proof_replayed_on_a_new_connection() {
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (tx, mut accepted) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            if tx.send(server.accept_and_handshake().await).is_err() {
+                break;
+            }
+        }
+    });
+
+    let client_key = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let (captured, finished) = handshake_by_hand(&client, addr, &client_key, None).await;
+    assert!(finished);
+    let seen = accepted.recv().await.unwrap().unwrap();
+    assert_eq!(seen.id, node_id(&client_key));
+
+    let (_, finished) = handshake_by_hand(&client, addr, &client_key, Some(captured)).await;
+    assert!(accepted.recv().await.unwrap().is_err());
+    assert!(!finished);
+}
@@ -0,0 +1,107 @@
+use p2rent::crypto::{NodeKeypair, generate_keypair, node_id};
+use p2rent::net::quic::{PeerAddr, QuicClient, QuicServer};
+use p2rent::net::server::{ServeConfig, serve_forever};
+use p2rent::peers::{KnownPeers, PeerCheck, connect_known};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Serves nothing in particular as the node `key`.
+async fn spawn_server_as(key: NodeKeypair) -> SocketAddr {
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), key)
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let config = ServeConfig::new(PathBuf::from("chunks"), PathBuf::from("manifests"));
+    tokio::spawn(serve_forever(server, Arc::new(config)));
+    addr
+}
+
+#[tokio::test]
+async fn first_node_at_an_address_is_remembered() {
+    let temp = tempfile::tempdir().unwrap();
+    let path = temp.path().join("p2rent/known_peers");
+    let mut known = KnownPeers::load(&path).unwrap();
+
+    let server_key = generate_keypair().unwrap();
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), server_key.clone())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut peers = Vec::new();
+        while let Ok(peer) = server.accept_and_handshake().await {
+            peers.push(peer);
+        }
+    });
+    let client = QuicClient::new().await.unwrap();
+    let peer = client
+        .connect_and_handshake(addr.parse().unwrap(), &generate_keypair().unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(known.check(&addr, &peer.id), PeerCheck::New);
+    assert_eq!(known.insert(&addr, &peer.id), None);
+    known.save().unwrap();
+
+    let mut known = KnownPeers::load(&path).unwrap();
+    assert_eq!(known.get(&addr), Some(&node_id(&server_key)));
+    assert_eq!(known.check(&addr, &peer.id), PeerCheck::Known);
+
+    // Another node answering at the same address is flagged until accepted.
+    let other = node_id(&generate_keypair().unwrap());
+    assert_eq!(
+        known.check(&addr, &other),
+        PeerCheck::Changed {
+            previous: peer.id.clone()
+        }
+    );
+    assert_eq!(known.insert(&addr, &other), Some(peer.id.clone()));
+    known.save().unwrap();
+    let known = KnownPeers::load(&path).unwrap();
+    assert_eq!(known.check(&addr, &other), PeerCheck::Known);
+
+    std::fs::write(&path, format!("# hand-edited\n{addr} {other}\nbroken\n")).unwrap();
+    assert!(KnownPeers::load(&path).is_err());
+}
+
+#[tokio::test]
+async fn different_node_at_a_known_address_is_refused() {
+    let temp = tempfile::tempdir().unwrap();
+    let path = temp.path().join("known_peers");
+    let server_key = generate_keypair().unwrap();
+    let addr = spawn_server_as(server_key.clone()).await;
+    let client = QuicClient::new().await.unwrap();
+    let keypair = generate_keypair().unwrap();
+    let unpinned = PeerAddr::parse(&addr.to_string()).unwrap();
+
+    // First contact is recorded and saved; the next is just checked.
+    let mut known = KnownPeers::load(&path).unwrap();
+    let (_, recorded) = connect_known(&client, &unpinned, &keypair, &mut known)
+        .await
+        .unwrap();
+    assert!(recorded);
+    let mut known = KnownPeers::load(&path).unwrap();
+    assert_eq!(known.get(&unpinned.addr), Some(&node_id(&server_key)));
+    let (_, recorded) = connect_known(&client, &unpinned, &keypair, &mut known)
+        .await
+        .unwrap();
+    assert!(!recorded);
+
+    // Another node now answers at that address.
+    let impostor = node_id(&generate_keypair().unwrap());
+    std::fs::write(&path, format!("{} {impostor}\n", unpinned.addr)).unwrap();
+    let mut known = KnownPeers::load(&path).unwrap();
+    let refused = connect_known(&client, &unpinned, &keypair, &mut known).await;
+    assert!(refused.is_err());
+    let known = KnownPeers::load(&path).unwrap();
+    assert_eq!(known.get(&unpinned.addr), Some(&impostor));
+
+    // A pinned address is checked against its pin, not the store.
+    let pinned = PeerAddr::parse(&format!("{}@{addr}", node_id(&server_key))).unwrap();
+    let mut known = KnownPeers::load(&path).unwrap();
+    let (peer, recorded) = connect_known(&client, &pinned, &keypair, &mut known)
+        .await
+        .unwrap();
+    assert_eq!((peer.id, recorded), (node_id(&server_key), false));
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{serve_config, share, spawn_server};
+use p2rent::crypto::generate_keypair;
+use p2rent::merkle::{self, MerkleTree, verify_range};
+use p2rent::net::download::fetch_chunk_list;
+use p2rent::net::quic::QuicClient;
+
+fn leaves(n: u64) -> Vec<([u8; 32], u64)> {
+    (0..n)
+        .map(|i| (*blake3::hash(&i.to_le_bytes()).as_bytes(), 100 + i))
+        .collect()
+}
+
+#[test]
+fn every_range_proves_and_tampering_fails() {
+    for n in 1..=11 {
+        let all = leaves(n);
+        let tree = MerkleTree::new(all.iter().copied());
+        let root = tree.root();
+        for start in 0..n {
+            for end in start + 1..=n {
+                let range = &all[start as usize..end as usize];
+                let proof = tree.prove(start, end);
+                assert!(
+                    verify_range(&root, n, start, range, &proof),
+                    "{n} {start}..{end}"
+                );
+
+                let mut resized = range.to_vec();
+                resized[0].1 += 1;
+                assert!(!verify_range(&root, n, start, &resized, &proof));
+                if end < n {
+                    assert!(!verify_range(&root, n, start + 1, range, &proof));
+                }
+            }
+        }
+    }
+    let empty = MerkleTree::new(Vec::new());
+    assert!(verify_range(&empty.root(), 0, 0, &[], &[]));
+}
+
+#[tokio::test]
+async fn chunk_list_is_fetched_by_content_id() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = (0..64 * 5000 + 9)
+        .map(|i| (i % 253) as u8)
+        .collect::<Vec<_>>();
+    let full = share(temp.path(), "big.bin", &data, 64);
+
+    let keypair = generate_keypair().unwrap();
+    let addr = spawn_server(serve_config(temp.path())).await;
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+
+    // More chunks than fit in one reply, so several proven batches.
+    let id = merkle::parse_content_id(full.root.as_deref().unwrap()).unwrap();
+    let list = fetch_chunk_list(std::slice::from_ref(&peer), &id)
+        .await
+        .unwrap();
+    assert_eq!(list.leaves, full.merkle_leaves());
+    assert_eq!(list.file_size, data.len() as u64);
+
+    let mut compact = full.compact();
+    assert!(compact.is_compact());
+    compact.fill_chunks(&list.leaves).unwrap();
+    assert_eq!(compact.chunks, full.chunks);
+
+    let mut unknown = id;
+    unknown[0] ^= 1;
+    assert!(fetch_chunk_list(&[peer], &unknown).await.is_err());
+}
@@ -0,0 +1,79 @@
+use p2rent::crypto::{generate_keypair, node_id};
+use p2rent::net::quic::{PeerAddr, QuicClient, QuicServer, cert_node_id};
+
+#[tokio::test]
+async fn server_is_pinned_by_its_tls_certificate() {
+    let server_key = generate_keypair().unwrap();
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), server_key.clone())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    // Refused clients fail the accept; keep serving the others.
+    tokio::spawn(async move {
+        let mut peers = Vec::new();
+        loop {
+            if let Ok(peer) = server.accept_and_handshake().await {
+                peers.push(peer);
+            }
+        }
+    });
+
+    let client_key = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let expected = node_id(&server_key);
+
+    let peer = client
+        .connect_and_handshake(addr, &client_key)
+        .await
+        .unwrap();
+    assert_eq!(peer.id, expected);
+
+    let peer = client
+        .connect_to_node(addr, &expected, &client_key)
+        .await
+        .unwrap();
+    assert_eq!(peer.id, expected);
+
+    let impostor = node_id(&generate_keypair().unwrap());
+    assert!(
+        client
+            .connect_to_node(addr, &impostor, &client_key)
+            .await
+            .is_err()
+    );
+
+    let pinned = PeerAddr::parse(&format!("{}@{addr}", expected.to_uppercase())).unwrap();
+    assert_eq!(pinned.node.as_deref(), Some(expected.as_str()));
+    assert_eq!(pinned.to_string(), format!("{expected}@{addr}"));
+    let peer = client.connect_peer(&pinned, &client_key).await.unwrap();
+    assert_eq!(peer.id, expected);
+    let wrong = PeerAddr::parse(&format!("{impostor}@{addr}")).unwrap();
+    assert!(client.connect_peer(&wrong, &client_key).await.is_err());
+
+    assert!(PeerAddr::parse("abc@127.0.0.1:5000").is_err());
+    assert_eq!(PeerAddr::parse("host:5000").unwrap().node, None);
+}
+
+#[tokio::test]
+async fn server_learns_the_client_node_from_its_certificate() {
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let accepted = tokio::spawn(async move { server.accept_and_handshake().await.unwrap() });
+
+    let client_key = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let _peer = client
+        .connect_and_handshake(addr, &client_key)
+        .await
+        .unwrap();
+    let seen = accepted.await.unwrap();
+    assert_eq!(seen.id, node_id(&client_key));
+
+    let cert = seen.connection.peer_identity().unwrap();
+    let certs = cert
+        .downcast::<Vec<rustls_pki_types::CertificateDer<'static>>>()
+        .unwrap();
+    assert_eq!(cert_node_id(&certs[0]).unwrap(), node_id(&client_key));
+}
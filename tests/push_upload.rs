@@ -0,0 +1,115 @@
+mod common;
+
+use common::spawn_server;
+use p2rent::chunk::split_file;
+use p2rent::crypto::{generate_keypair, node_id};
+use p2rent::manifest::{self, AnyManifest, Manifest};
+use p2rent::net::push::push;
+use p2rent::net::quic::QuicClient;
+use p2rent::net::server::{ServeConfig, handle_peer};
+use p2rent::storage;
+use p2rent::sync::{ShareOptions, sync_directory};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn pushed_share_is_pulled_and_served() {
+    let temp = tempfile::tempdir().unwrap();
+    let local_chunks = temp.path().join("local/chunks");
+    let local_manifests = temp.path().join("local/manifests");
+    let file_path = temp.path().join("notes.txt");
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    std::fs::write(&file_path, &data).unwrap();
+    let chunks = split_file(&file_path, 8 * 1024).unwrap();
+    for c in &chunks {
+        storage::save_object(&local_chunks, c).unwrap();
+    }
+    let offered = Manifest::from_chunks("notes.txt".into(), 8 * 1024, &chunks);
+    let share = offered.share_id();
+    manifest::write_manifest(&offered, &manifest::manifest_path(&local_manifests, &share)).unwrap();
+
+    let pusher = generate_keypair().unwrap();
+    let stranger = generate_keypair().unwrap();
+    let remote_chunks = temp.path().join("remote/chunks");
+    let remote_manifests = temp.path().join("remote/manifests");
+    let addr = spawn_server(
+        ServeConfig::new(remote_chunks.clone(), remote_manifests.clone())
+            .accept_push_from(vec![node_id(&pusher)]),
+    )
+    .await;
+
+    let client = QuicClient::new().await.unwrap();
+    let local = Arc::new(ServeConfig::new(local_chunks, local_manifests));
+    let offer = AnyManifest::File(offered.clone());
+
+    let peer = client.connect_and_handshake(addr, &stranger).await.unwrap();
+    tokio::spawn(handle_peer(peer.clone(), local.clone()));
+    assert!(push(&peer, &offer).await.is_err());
+    assert!(!manifest::manifest_path(&remote_manifests, &share).exists());
+
+    let peer = client.connect_and_handshake(addr, &pusher).await.unwrap();
+    tokio::spawn(handle_peer(peer.clone(), local.clone()));
+    let report = push(&peer, &offer).await.unwrap();
+    assert_eq!(report.fetched, chunks.len() as u64);
+    assert_eq!(report.present, 0);
+    for c in &chunks {
+        assert!(storage::has_object(&remote_chunks, &c.hash));
+    }
+    let stored =
+        manifest::read_manifest(&manifest::manifest_path(&remote_manifests, &share)).unwrap();
+    assert_eq!(stored.root, offered.root);
+    assert_eq!(stored.chunks, offered.chunks);
+
+    // A second push finds everything in place.
+    let report = push(&peer, &offer).await.unwrap();
+    assert_eq!(report.fetched, 0);
+    assert_eq!(report.present, chunks.len() as u64);
+}
+
+#[tokio::test]
+async fn pushed_directory_gets_its_chunk_lists_from_the_pusher() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("album");
+    std::fs::create_dir_all(root.join("2024")).unwrap();
+    std::fs::write(root.join("cover.txt"), vec![b'c'; 3000]).unwrap();
+    std::fs::write(root.join("2024/a.txt"), vec![b'a'; 9000]).unwrap();
+    let local_chunks = temp.path().join("local/chunks");
+    let local_manifests = temp.path().join("local/manifests");
+    let tree = sync_directory(
+        &root,
+        &ShareOptions::new(1024, &local_chunks, &local_manifests),
+    )
+    .unwrap();
+
+    let pusher = generate_keypair().unwrap();
+    let remote_chunks = temp.path().join("remote/chunks");
+    let remote_manifests = temp.path().join("remote/manifests");
+    let addr = spawn_server(
+        ServeConfig::new(remote_chunks.clone(), remote_manifests.clone())
+            .accept_push_from(vec![node_id(&pusher)]),
+    )
+    .await;
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &pusher).await.unwrap();
+    let local = Arc::new(ServeConfig::new(local_chunks, local_manifests));
+    tokio::spawn(handle_peer(peer.clone(), local));
+
+    let offered = AnyManifest::Directory(tree);
+    push(&peer, &offered).await.unwrap();
+    let AnyManifest::Directory(tree) = offered else {
+        unreachable!()
+    };
+    let stored = manifest::directory_manifest_path(&remote_manifests, "album");
+    let AnyManifest::Directory(stored) = manifest::read_any_manifest(&stored).unwrap() else {
+        panic!("expected a directory manifest");
+    };
+    for (sent, kept) in tree.files.iter().zip(&stored.files) {
+        assert!(!sent.manifest.chunks.is_empty());
+        assert_eq!(kept.manifest.chunks, sent.manifest.chunks);
+        assert!(
+            sent.manifest
+                .chunks
+                .iter()
+                .all(|h| storage::has_object(&remote_chunks, h))
+        );
+    }
+}
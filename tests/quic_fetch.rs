@@ -1,5 +1,5 @@
 use p2rent::chunk::split_file;
-use p2rent::crypto::load_or_create_keypair;
+use p2rent::crypto::generate_keypair;
 use p2rent::manifest::{self, Manifest};
 use p2rent::net::protocol::Message;
 use p2rent::net::quic::{self, QuicClient, QuicServer};
@@ -25,7 +25,7 @@ async fn quic_request_chunk_roundtrip() {
     let manifest = Manifest::from_chunks("hello.txt".into(), 8, &chunks);
     manifest::write_manifest(&manifest, &manifest_dir.join("hello.manifest.json")).unwrap();
 
-    let keypair = load_or_create_keypair().unwrap();
+    let keypair = generate_keypair().unwrap();
     let addr: std::net::SocketAddr = "127.0.0.1:5600".parse().unwrap();
     let server = QuicServer::bind(addr, keypair.clone()).await.unwrap();
 
@@ -36,11 +36,11 @@ async fn quic_request_chunk_roundtrip() {
                 let serve_dir = serve_dir.clone();
                 tokio::spawn(async move {
                     while let Ok((mut send, mut recv)) = peer.connection.accept_bi().await {
-                        if let Ok(Message::RequestChunk { stem, index }) =
+                        if let Ok(Message::RequestChunk { share, index }) =
                             quic::receive_message(&mut recv).await
                         {
                             let mut dir = serve_dir.clone();
-                            dir.push(stem);
+                            dir.push(share);
                             if let Ok(ch) = storage::load_chunk(dir.to_str().unwrap(), index) {
                                 let _ = quic::send_message(
                                     &mut send,
@@ -64,7 +64,7 @@ async fn quic_request_chunk_roundtrip() {
     quic::send_message(
         &mut send,
         &Message::RequestChunk {
-            stem: stem.into(),
+            share: stem.into(),
             index: 0,
         },
     )
@@ -0,0 +1,117 @@
+mod common;
+
+use common::{noise, spawn_server};
+use p2rent::chunk::Chunk;
+use p2rent::crypto::generate_keypair;
+use p2rent::manifest::{self, AnyManifest, DirectoryEntry, DirectoryManifest, Manifest};
+use p2rent::merkle;
+use p2rent::net::download::{fetch_manifest, fetch_shared_manifest};
+use p2rent::net::quic::{MAX_MESSAGE_SIZE, QuicClient};
+use p2rent::net::server::ServeConfig;
+use p2rent::sync::{ShareOptions, sync_directory};
+
+#[tokio::test]
+async fn manifests_are_fetched_by_share_id_or_content_id() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("project");
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::write(root.join("docs/a.txt"), b"alpha").unwrap();
+    std::fs::write(root.join("b.txt"), b"bravo bravo").unwrap();
+    let manifest_dir = temp.path().join("manifests");
+    let storage_dir = temp.path().join("chunks");
+    let tree = sync_directory(&root, &ShareOptions::new(4, &storage_dir, &manifest_dir)).unwrap();
+
+    let keypair = generate_keypair().unwrap();
+    let addr = spawn_server(ServeConfig::new(temp.path().join("chunks"), manifest_dir)).await;
+    let client = QuicClient::new().await.unwrap();
+    let peers = [client.connect_and_handshake(addr, &keypair).await.unwrap()];
+
+    let AnyManifest::File(file) = fetch_shared_manifest(&peers, "project/docs/a.txt")
+        .await
+        .unwrap()
+    else {
+        panic!("expected a file manifest");
+    };
+    assert_eq!(file.file_size, 5);
+
+    let AnyManifest::Directory(dir) = fetch_shared_manifest(&peers, "project").await.unwrap()
+    else {
+        panic!("expected a directory manifest");
+    };
+    let paths: Vec<_> = dir.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["b.txt", "docs/a.txt"]);
+
+    for missing in ["nope", "../manifests/project", "/etc/passwd", ""] {
+        assert!(
+            fetch_shared_manifest(&peers, missing).await.is_err(),
+            "{missing}"
+        );
+    }
+
+    let b = &tree
+        .files
+        .iter()
+        .find(|f| f.path == "b.txt")
+        .unwrap()
+        .manifest;
+    let id = merkle::parse_content_id(b.root.as_deref().unwrap()).unwrap();
+    let fetched = fetch_manifest(&peers, &id).await.unwrap();
+    assert_eq!(fetched.chunks, b.chunks);
+    assert_eq!(fetched.share, "project/b.txt");
+}
+
+#[tokio::test]
+async fn manifest_larger_than_a_message_is_fetched() {
+    let temp = tempfile::tempdir().unwrap();
+    let manifest_dir = temp.path().join("manifests");
+    // One-byte chunks: 160k hashes make a manifest beyond the message cap.
+    let data = noise(160_000, 17);
+    let mut big = Manifest::new("big.bin".into(), 1);
+    for (index, byte) in data.iter().enumerate() {
+        big.push_chunk(&Chunk {
+            index: index as u64,
+            hash: blake3::hash(&[*byte]).into(),
+            data: vec![*byte],
+            size: 1,
+        });
+    }
+    big.share = "big/big.bin".into();
+    big.set_root();
+    assert!(serde_json::to_vec(&big).unwrap().len() > MAX_MESSAGE_SIZE);
+    manifest::write_manifest(&big, &manifest::manifest_path(&manifest_dir, &big.share)).unwrap();
+    let tree = DirectoryManifest {
+        name: "big".into(),
+        files: vec![DirectoryEntry {
+            path: "big.bin".into(),
+            mode: 0o644,
+            mtime: 0,
+            manifest: big.clone(),
+        }],
+        empty_dirs: Vec::new(),
+    };
+    manifest::write_directory_manifest(
+        &tree,
+        &manifest::directory_manifest_path(&manifest_dir, "big"),
+    )
+    .unwrap();
+
+    let keypair = generate_keypair().unwrap();
+    let addr = spawn_server(ServeConfig::new(temp.path().join("chunks"), manifest_dir)).await;
+    let client = QuicClient::new().await.unwrap();
+    let peers = [client.connect_and_handshake(addr, &keypair).await.unwrap()];
+
+    let id = merkle::parse_content_id(big.root.as_deref().unwrap()).unwrap();
+    let fetched = fetch_manifest(&peers, &id).await.unwrap();
+    assert_eq!(fetched.chunks, big.chunks);
+
+    let AnyManifest::File(shared) = fetch_shared_manifest(&peers, "big/big.bin").await.unwrap()
+    else {
+        panic!("expected a file manifest");
+    };
+    assert_eq!(shared.chunks, big.chunks);
+
+    let AnyManifest::Directory(dir) = fetch_shared_manifest(&peers, "big").await.unwrap() else {
+        panic!("expected a directory manifest");
+    };
+    assert_eq!(dir.files[0].manifest.chunks, big.chunks);
+}
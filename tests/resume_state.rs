@@ -0,0 +1,94 @@
+mod common;
+
+use common::{noise, serve_config, share, spawn_server};
+use p2rent::chunk::split_file;
+use p2rent::crypto::generate_keypair;
+use p2rent::fetch::{FetchTarget, FileFetch};
+use p2rent::manifest::Manifest;
+use p2rent::net::quic::QuicClient;
+use p2rent::resume::{self, ResumeState};
+use p2rent::storage;
+use tokio::sync::Notify;
+
+#[test]
+fn resume_record_roundtrip_and_manifest_binding() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("data.bin");
+    std::fs::write(&file_path, vec![7u8; 1000]).unwrap();
+    let chunks = split_file(&file_path, 100).unwrap();
+    let manifest = Manifest::from_chunks("data.bin".into(), 100, &chunks);
+
+    let out = dir.path().join("out.bin");
+    let path = resume::resume_path(&out);
+    assert!(resume::load_resume(&path).unwrap().is_none());
+
+    let mut state = ResumeState::new(&manifest);
+    state.completed.set(3);
+    state.completed.set(9);
+    resume::save_resume(&state, &path).unwrap();
+
+    let loaded = resume::load_resume(&path).unwrap().unwrap();
+    assert!(loaded.matches(&manifest));
+    assert_eq!(loaded.completed.iter_ones().collect::<Vec<_>>(), vec![3, 9]);
+
+    let other = Manifest::from_chunks("data.bin".into(), 200, &chunks);
+    assert!(!loaded.matches(&other));
+}
+
+#[tokio::test]
+async fn interrupted_fetch_resumes_without_refetching() {
+    let temp = tempfile::tempdir().unwrap();
+    let seeder = temp.path().join("seeder");
+    std::fs::create_dir_all(&seeder).unwrap();
+    let data = noise(64 * 120, 9);
+    let manifest = share(&seeder, "data.bin", &data, 64);
+    let addr = spawn_server(serve_config(&seeder)).await;
+
+    let out_path = temp.path().join("out.bin");
+    let target = FetchTarget {
+        share: manifest.share.clone(),
+        seed_from: out_path.clone(),
+        out_path: out_path.clone(),
+        manifest: manifest.clone(),
+    };
+    let keypair = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+
+    // Interrupt once a handful of chunks have been written.
+    let stop = Notify::new();
+    let mut written = 0;
+    let result = FileFetch::open(&target, None)
+        .unwrap()
+        .unwrap()
+        .download(std::slice::from_ref(&peer), 1, stop.notified(), || {
+            written += 1;
+            if written == 10 {
+                stop.notify_one();
+            }
+        })
+        .await;
+    assert!(result.is_err());
+    assert!(!out_path.exists());
+
+    // The seeder loses every chunk the first attempt kept, so asking for
+    // any of them again would fail the fetch.
+    let state = resume::load_resume(&resume::resume_path(&out_path))
+        .unwrap()
+        .unwrap();
+    let kept = state.completed.iter_ones().collect::<Vec<_>>();
+    assert!(kept.len() >= 10 && kept.len() < manifest.chunks.len());
+    for &index in &kept {
+        let hash = manifest.chunks[index as usize];
+        std::fs::remove_file(storage::object_path(&seeder.join("chunks"), &hash)).unwrap();
+    }
+
+    let fetch = FileFetch::open(&target, None).unwrap().unwrap();
+    assert_eq!(fetch.on_disk(), kept.len() as u64);
+    fetch
+        .download(&[peer], 1, std::future::pending(), || {})
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&out_path).unwrap(), data);
+    assert!(!resume::resume_path(&out_path).exists());
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{noise, serve_config, share, spawn_server};
+use p2rent::crypto::generate_keypair;
+use p2rent::fetch::{self, FetchTarget, FileFetch};
+use p2rent::manifest::{self, Manifest};
+use p2rent::net::quic::QuicClient;
+use std::future::pending;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// Fetches `manifest` from the node at `addr` into `dir`, seeding it into
+/// `dir`'s store when `seed` is set.
+async fn fetch_into(dir: &Path, addr: SocketAddr, manifest: &Manifest, seed: bool) {
+    let out_path = dir.join("out.bin");
+    let target = FetchTarget {
+        share: manifest.share.clone(),
+        seed_from: out_path.clone(),
+        out_path,
+        manifest: manifest.clone(),
+    };
+    let seed_dir = dir.join("chunks");
+    if seed {
+        fetch::seed_manifests(&dir.join("manifests"), std::slice::from_ref(&target), None).unwrap();
+    }
+    let client = QuicClient::new().await.unwrap();
+    let peer = client
+        .connect_and_handshake(addr, &generate_keypair().unwrap())
+        .await
+        .unwrap();
+    FileFetch::open(&target, seed.then_some(seed_dir.as_path()))
+        .unwrap()
+        .unwrap()
+        .download(&[peer], 4, pending(), || {})
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn seeded_fetch_is_served_to_the_next_peer() {
+    let temp = tempfile::tempdir().unwrap();
+    let (a, b, c) = (
+        temp.path().join("a"),
+        temp.path().join("b"),
+        temp.path().join("c"),
+    );
+    let data = noise(5000, 3);
+    std::fs::create_dir_all(&a).unwrap();
+    let manifest = share(&a, "data.bin", &data, 256);
+    let a_addr = spawn_server(serve_config(&a)).await;
+
+    fetch_into(&b, a_addr, &manifest, true).await;
+    let b_addr = spawn_server(serve_config(&b)).await;
+
+    // C only knows B, which serves what it fetched.
+    fetch_into(&c, b_addr, &manifest, false).await;
+    assert_eq!(std::fs::read(c.join("out.bin")).unwrap(), data);
+}
+
+#[test]
+fn seeding_refuses_to_repoint_a_share() {
+    let temp = tempfile::tempdir().unwrap();
+    let manifest_dir = temp.path().join("manifests");
+    // `share` records the first version under temp/manifests.
+    let first = share(temp.path(), "report.pdf", b"first report", 4);
+    let other = temp.path().join("other");
+    std::fs::create_dir_all(&other).unwrap();
+    let second = share(&other, "report.pdf", b"second", 4);
+
+    let target = |m: &Manifest| FetchTarget {
+        share: m.share.clone(),
+        out_path: temp.path().join("out.pdf"),
+        seed_from: temp.path().join("out.pdf"),
+        manifest: m.clone(),
+    };
+    // The same content may be seeded again; different content may not.
+    fetch::seed_manifests(&manifest_dir, &[target(&first)], None).unwrap();
+    assert!(fetch::seed_manifests(&manifest_dir, &[target(&second)], None).is_err());
+    let kept = manifest::read_manifest(&manifest::manifest_path(&manifest_dir, "report.pdf"));
+    assert_eq!(kept.unwrap().chunks, first.chunks);
+}
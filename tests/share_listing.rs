@@ -0,0 +1,113 @@
+mod common;
+
+use common::spawn_server;
+use p2rent::crypto::{generate_keypair, node_id};
+use p2rent::manifest::{self, Manifest};
+use p2rent::net::download::list_shares;
+use p2rent::net::protocol::{MAX_SHARES_PER_REPLY, Message};
+use p2rent::net::quic::{self, Peer, QuicClient};
+use p2rent::net::server::ServeConfig;
+use p2rent::sync::{ShareOptions, sync_directory};
+
+#[tokio::test]
+async fn catalog_is_listed_across_pages() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("logs");
+    std::fs::create_dir_all(&root).unwrap();
+    let files = MAX_SHARES_PER_REPLY + 20;
+    for i in 0..files {
+        std::fs::write(root.join(format!("{i:03}.log")), format!("line {i}\n")).unwrap();
+    }
+    let manifest_dir = temp.path().join("manifests");
+    let storage_dir = temp.path().join("chunks");
+    sync_directory(&root, &ShareOptions::new(4, &storage_dir, &manifest_dir)).unwrap();
+
+    let publisher = generate_keypair().unwrap();
+    let mut signed = Manifest::new("release.tar".into(), 4);
+    signed.set_root();
+    signed.sign(&publisher).unwrap();
+    manifest::write_manifest(
+        &signed,
+        &manifest::manifest_path(&manifest_dir, "release.tar"),
+    )
+    .unwrap();
+
+    let keypair = generate_keypair().unwrap();
+    let addr = spawn_server(ServeConfig::new(temp.path().join("chunks"), manifest_dir)).await;
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+
+    let shares = list_shares(&peer).await.unwrap();
+    // Every file, the directory itself and the signed release.
+    assert_eq!(shares.len() as u64, files + 2);
+    assert!(shares.windows(2).all(|w| w[0].share < w[1].share));
+
+    let dir = shares.iter().find(|s| s.share == "logs").unwrap();
+    assert!(dir.directory && dir.root.is_none());
+    assert_eq!(
+        dir.size,
+        shares
+            .iter()
+            .filter(|s| s.share.starts_with("logs/"))
+            .map(|s| s.size)
+            .sum::<u64>()
+    );
+
+    let first = shares.iter().find(|s| s.share == "logs/000.log").unwrap();
+    assert!(!first.directory);
+    assert_eq!((first.size, first.chunks), (7, 2));
+    assert!(first.root.is_some() && first.publisher.is_none());
+
+    let release = shares.iter().find(|s| s.share == "release.tar").unwrap();
+    assert_eq!(release.publisher, Some(node_id(&publisher)));
+}
+
+/// Asks `peer` for one page of its catalog: `(total, shares on the page)`.
+async fn page(peer: &Peer, start: u64) -> (u64, usize) {
+    let (mut send, mut recv) = peer.connection.open_bi().await.unwrap();
+    let req = Message::ListShares {
+        start,
+        count: MAX_SHARES_PER_REPLY,
+    };
+    quic::send_message(&mut send, &req).await.unwrap();
+    let Message::Shares { total, shares, .. } = quic::receive_message(&mut recv).await.unwrap()
+    else {
+        panic!("expected a page of shares");
+    };
+    (total, shares.len())
+}
+
+#[tokio::test]
+async fn later_pages_come_from_the_connection_snapshot() {
+    let temp = tempfile::tempdir().unwrap();
+    let manifest_dir = temp.path().join("manifests");
+    let publish = |name: &str| {
+        manifest::write_manifest(
+            &Manifest::new(name.into(), 4),
+            &manifest::manifest_path(&manifest_dir, name),
+        )
+        .unwrap()
+    };
+    let files = MAX_SHARES_PER_REPLY + 5;
+    for i in 0..files {
+        publish(&format!("{i:03}.txt"));
+    }
+
+    let addr = spawn_server(ServeConfig::new(
+        temp.path().join("chunks"),
+        manifest_dir.clone(),
+    ))
+    .await;
+    let client = QuicClient::new().await.unwrap();
+    let peer = client
+        .connect_and_handshake(addr, &generate_keypair().unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(page(&peer, 0).await.0, files);
+    publish("new.txt");
+    // The listing in progress keeps paging through what it started with.
+    assert_eq!(page(&peer, MAX_SHARES_PER_REPLY).await, (files, 5));
+    // Starting over picks up the change.
+    assert_eq!(page(&peer, 0).await.0, files + 1);
+}
@@ -0,0 +1,26 @@
+use p2rent::manifest;
+use p2rent::sync::{ShareOptions, share_file};
+
+#[test]
+fn same_named_file_does_not_replace_a_share_silently() {
+    let temp = tempfile::tempdir().unwrap();
+    let (storage_dir, manifest_dir) = (temp.path().join("chunks"), temp.path().join("manifests"));
+    let (a, b) = (temp.path().join("a"), temp.path().join("b"));
+    for (dir, text) in [(&a, "first report"), (&b, "second report")] {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("report.pdf"), text).unwrap();
+    }
+    let (a, b) = (a.join("report.pdf"), b.join("report.pdf"));
+    let options = ShareOptions::new(4, &storage_dir, &manifest_dir);
+    let path = manifest::manifest_path(&manifest_dir, "report.pdf");
+
+    let first = share_file(&a, &a, &options, |_, _| {}).unwrap();
+    // Sharing the same content again is fine.
+    share_file(&a, &a, &options, |_, _| {}).unwrap();
+    assert!(share_file(&b, &b, &options, |_, _| {}).is_err());
+    assert_eq!(manifest::read_manifest(&path).unwrap().root, first.root);
+
+    let second = share_file(&b, &b, &options.replace(true), |_, _| {}).unwrap();
+    assert_ne!(second.root, first.root);
+    assert_eq!(manifest::read_manifest(&path).unwrap().root, second.root);
+}
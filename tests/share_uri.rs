@@ -0,0 +1,76 @@
+mod common;
+
+use common::spawn_server;
+use p2rent::chunk::split_file;
+use p2rent::crypto::{generate_keypair, node_id};
+use p2rent::manifest::{self, Manifest};
+use p2rent::net::download::fetch_manifest;
+use p2rent::net::quic::QuicClient;
+use p2rent::net::server::ServeConfig;
+use p2rent::storage;
+use p2rent::uri::ShareUri;
+
+#[test]
+fn links_round_trip_and_reject_bad_input() {
+    let uri = ShareUri {
+        root: *blake3::hash(b"x").as_bytes(),
+        name: Some("Q3 report & notes (final).pdf".into()),
+        publisher: Some(blake3::hash(b"pub").to_hex().to_string()),
+        peers: vec!["10.0.0.2:5000".into(), "[::1]:5001".into()],
+    };
+    let text = uri.to_string();
+    assert!(text.starts_with("p2rent://"));
+    assert!(!text.contains(' '));
+    assert_eq!(text.parse::<ShareUri>().unwrap(), uri);
+
+    let bare = ShareUri::parse(&format!("p2rent://{}", blake3::hash(b"x").to_hex())).unwrap();
+    assert_eq!(bare.root, uri.root);
+    assert!(bare.name.is_none() && bare.publisher.is_none() && bare.peers.is_empty());
+
+    let id = blake3::hash(b"x").to_hex();
+    for bad in [
+        format!("magnet:?xt={id}"),
+        "p2rent://abc".to_string(),
+        format!("p2rent://{id}?name=..%2Fetc%2Fpasswd"),
+        format!("p2rent://{id}?pub=nope"),
+        format!("p2rent://{id}?name=%zz"),
+    ] {
+        assert!(ShareUri::parse(&bad).is_err(), "{bad}");
+    }
+}
+
+#[tokio::test]
+async fn manifest_is_fetched_by_content_id() {
+    let temp = tempfile::tempdir().unwrap();
+    let file_path = temp.path().join("notes.txt");
+    std::fs::write(&file_path, vec![3u8; 50_000]).unwrap();
+    let chunks = split_file(&file_path, 8192).unwrap();
+    for c in &chunks {
+        storage::save_object(&temp.path().join("chunks"), c).unwrap();
+    }
+    let keypair = generate_keypair().unwrap();
+    let mut m = Manifest::from_chunks("notes.txt".into(), 8192, &chunks);
+    m.sign(&keypair).unwrap();
+    let manifest_dir = temp.path().join("manifests");
+    manifest::write_manifest(&m, &manifest::manifest_path(&manifest_dir, &m.share)).unwrap();
+
+    let addr = spawn_server(ServeConfig::new(temp.path().join("chunks"), manifest_dir)).await;
+
+    let link = ShareUri::for_manifest(&m, vec![addr.to_string()]).unwrap();
+    assert_eq!(link.publisher, Some(node_id(&keypair)));
+    let link = ShareUri::parse(&link.to_string()).unwrap();
+
+    let client = QuicClient::new().await.unwrap();
+    let peer = client
+        .connect_and_handshake(link.peers[0].parse().unwrap(), &keypair)
+        .await
+        .unwrap();
+    let peers = [peer];
+    let fetched = fetch_manifest(&peers, &link.root).await.unwrap();
+    assert_eq!(fetched.chunks, m.chunks);
+    assert_eq!(fetched.verify_signature().unwrap(), link.publisher);
+
+    let mut unknown = link.root;
+    unknown[5] ^= 1;
+    assert!(fetch_manifest(&peers, &unknown).await.is_err());
+}
@@ -0,0 +1,72 @@
+use p2rent::chunk::split_file;
+use p2rent::crypto::{generate_keypair, node_id};
+use p2rent::manifest::{self, Manifest, PublisherPolicy};
+
+fn manifest() -> Manifest {
+    let temp = tempfile::tempdir().unwrap();
+    let path = temp.path().join("report.pdf");
+    std::fs::write(&path, vec![7u8; 10_000]).unwrap();
+    let chunks = split_file(&path, 4096).unwrap();
+    Manifest::from_chunks("report.pdf".into(), 4096, &chunks)
+}
+
+#[test]
+fn signatures_name_the_publisher_and_catch_edits() {
+    let publisher = generate_keypair().unwrap();
+    let mut m = manifest();
+    assert_eq!(m.verify_signature().unwrap(), None);
+    m.sign(&publisher).unwrap();
+
+    // Survives a round trip through the JSON file.
+    let temp = tempfile::tempdir().unwrap();
+    let path = manifest::manifest_path(temp.path(), &m.share);
+    manifest::write_manifest(&m, &path).unwrap();
+    let m = manifest::read_manifest(&path).unwrap();
+    assert_eq!(m.verify_signature().unwrap(), Some(node_id(&publisher)));
+    assert!(m.compact().verify_signature().is_ok());
+
+    let mut renamed = m.clone();
+    renamed.file_name = "invoice.pdf".into();
+    assert!(renamed.verify_signature().is_err());
+
+    let mut other_root = m.clone();
+    other_root.file_size += 1;
+    other_root.set_root();
+    assert!(other_root.verify_signature().is_err());
+
+    let mut stripped = m.clone();
+    stripped.signature = None;
+    assert!(stripped.verify_signature().is_err());
+}
+
+#[test]
+fn policy_rejects_unsigned_and_untrusted_manifests() {
+    let publisher = generate_keypair().unwrap();
+    let stranger = generate_keypair().unwrap();
+    let unsigned = manifest();
+    let mut signed = unsigned.clone();
+    signed.sign(&publisher).unwrap();
+
+    let open = PublisherPolicy::default();
+    assert_eq!(open.check(&unsigned).unwrap(), None);
+    assert!(open.check(&signed).unwrap().is_some());
+
+    let strict = PublisherPolicy {
+        require_signed: true,
+        trusted: Vec::new(),
+    };
+    assert!(strict.check(&unsigned).is_err());
+    assert!(strict.check(&signed).is_ok());
+
+    let pinned = PublisherPolicy {
+        require_signed: false,
+        trusted: vec![node_id(&stranger)],
+    };
+    assert!(pinned.check(&unsigned).is_err());
+    assert!(pinned.check(&signed).is_err());
+    let pinned = PublisherPolicy {
+        trusted: vec![node_id(&publisher)],
+        ..pinned
+    };
+    assert_eq!(pinned.check(&signed).unwrap(), Some(node_id(&publisher)));
+}
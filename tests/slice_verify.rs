@@ -0,0 +1,121 @@
+mod common;
+
+use common::{noise, spawn_server};
+use p2rent::chunk::split_file;
+use p2rent::crypto::generate_keypair;
+use p2rent::manifest::Manifest;
+use p2rent::net::download::{fetch_slice, read_range};
+use p2rent::net::quic::QuicClient;
+use p2rent::net::server::ServeConfig;
+use p2rent::outboard::{self, GROUP_LEN, SliceVerifier};
+use p2rent::storage;
+
+/// Runs every group of `data[start..end]` (widened to groups) through a
+/// verifier built from `nodes`.
+fn check(hash: &[u8; 32], data: &[u8], start: u64, end: u64, nodes: &[outboard::Node]) -> bool {
+    let len = data.len() as u64;
+    let Ok(mut v) = SliceVerifier::new(hash, len, start, end, nodes) else {
+        return false;
+    };
+    let (mut at, _) = outboard::group_range(len, start, end);
+    while let Some(n) = v.next_len() {
+        if v.verify(&data[at as usize..at as usize + n]).is_err() {
+            return false;
+        }
+        at += n as u64;
+    }
+    true
+}
+
+#[test]
+fn slices_verify_against_the_chunk_hash() {
+    let g = GROUP_LEN;
+    for len in [
+        1,
+        1024,
+        g - 1,
+        g,
+        g + 1,
+        2 * g,
+        3 * g + 7,
+        100_000,
+        (1 << 20) + 3,
+    ] {
+        let mut data = noise(len as usize, len);
+        let hash = *blake3::hash(&data).as_bytes();
+        let ob = outboard::outboard(&data);
+        assert_eq!(ob.len() as u64, outboard::outboard_len(len));
+
+        let ranges = [(0, len), (0, 1), (len - 1, len), (len / 3, len / 2 + 1)];
+        for (start, end) in ranges {
+            if start >= end {
+                continue;
+            }
+            let nodes = outboard::slice_nodes(&ob, len, start, end).unwrap();
+            assert!(
+                check(&hash, &data, start, end, &nodes),
+                "{len} {start}..{end}"
+            );
+
+            let mut other = hash;
+            other[0] ^= 1;
+            assert!(!check(&other, &data, start, end, &nodes));
+        }
+
+        // A flipped byte fails the group that holds it, and only that one.
+        let bad = len / 2;
+        data[bad as usize] ^= 0x40;
+        let nodes = outboard::slice_nodes(&ob, len, bad, bad + 1).unwrap();
+        assert!(!check(&hash, &data, bad, bad + 1, &nodes));
+        if bad >= g {
+            let nodes = outboard::slice_nodes(&ob, len, 0, 1).unwrap();
+            assert!(check(&hash, &data, 0, 1, &nodes));
+        }
+    }
+}
+
+#[tokio::test]
+async fn exact_ranges_are_read_and_corruption_is_caught() {
+    let temp = tempfile::tempdir().unwrap();
+    let storage_dir = temp.path().join("chunks");
+    let file_path = temp.path().join("data.bin");
+    let data = noise(300_000, 5);
+    std::fs::write(&file_path, &data).unwrap();
+    let chunks = split_file(&file_path, 128 * 1024).unwrap();
+    for c in &chunks {
+        storage::save_object(&storage_dir, c).unwrap();
+    }
+    let manifest = Manifest::from_chunks("data.bin".into(), 128 * 1024, &chunks);
+
+    // Objects stored before outboards existed get one on first use.
+    std::fs::remove_file(storage::outboard_path(&storage_dir, &chunks[1].hash)).unwrap();
+
+    let keypair = generate_keypair().unwrap();
+    let addr = spawn_server(ServeConfig::new(
+        storage_dir.clone(),
+        temp.path().join("manifests"),
+    ))
+    .await;
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+    let peers = std::slice::from_ref(&peer);
+
+    for (start, end) in [(0, 1), (5000, 5003), (131_000, 140_000), (0, 300_000)] {
+        let got = read_range(peers, &manifest, start, end).await.unwrap();
+        assert_eq!(got, &data[start as usize..end as usize]);
+    }
+    assert!(storage::outboard_path(&storage_dir, &chunks[1].hash).is_file());
+
+    // Damage the stored chunk: slices over the damage fail, others still
+    // verify.
+    let object = storage::object_path(&storage_dir, &chunks[0].hash);
+    let mut stored = std::fs::read(&object).unwrap();
+    stored[70_000] ^= 1;
+    std::fs::write(&object, &stored).unwrap();
+    let len = chunks[0].size as u64;
+    let hash = &chunks[0].hash;
+    assert!(fetch_slice(&peer, hash, len, 0, len).await.is_err());
+    assert!(fetch_slice(&peer, hash, len, 69_999, 70_001).await.is_err());
+    let ok = fetch_slice(&peer, hash, len, 100, 200).await.unwrap();
+    assert_eq!(ok, &data[100..200]);
+}
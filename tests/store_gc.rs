@@ -0,0 +1,118 @@
+mod common;
+
+use common::share;
+use p2rent::gc::{GcOptions, collect_garbage};
+use p2rent::storage::{self, StoreLock};
+
+const DRY_RUN: GcOptions = GcOptions {
+    dry_run: true,
+    allow_empty: false,
+};
+
+#[test]
+fn gc_removes_only_unreferenced_chunks() {
+    let temp = tempfile::tempdir().unwrap();
+    let storage_dir = temp.path().join("chunks");
+    let manifest_dir = temp.path().join("manifests");
+    let kept = share(temp.path(), "kept.bin", &[1u8; 64 * 3], 64);
+    let dropped = share(
+        temp.path(),
+        "dropped.bin",
+        &(0..200u8).collect::<Vec<_>>(),
+        64,
+    );
+    std::fs::remove_file(manifest_dir.join("dropped.bin.manifest.json")).unwrap();
+
+    let dry = collect_garbage(&storage_dir, &manifest_dir, DRY_RUN).unwrap();
+    assert_eq!(dry.manifests, 1);
+    assert_eq!(dry.removed.len(), dropped.chunks.len());
+    assert_eq!(dry.bytes_freed, 200);
+    assert!(
+        dropped
+            .chunks
+            .iter()
+            .all(|h| storage::has_object(&storage_dir, h))
+    );
+
+    // A running serve or share holds the store shared.
+    let lock = StoreLock::shared(&storage_dir).unwrap();
+    assert!(collect_garbage(&storage_dir, &manifest_dir, GcOptions::default()).is_err());
+    drop(lock);
+
+    let report = collect_garbage(&storage_dir, &manifest_dir, GcOptions::default()).unwrap();
+    assert_eq!(report.removed, dry.removed);
+    assert!(
+        !dropped
+            .chunks
+            .iter()
+            .any(|h| storage::has_object(&storage_dir, h))
+    );
+    for h in &kept.chunks {
+        storage::load_object(&storage_dir, h).unwrap();
+    }
+}
+
+#[test]
+fn gc_sweeps_only_what_it_owns() {
+    let temp = tempfile::tempdir().unwrap();
+    let storage_dir = temp.path().join("chunks");
+    let manifest_dir = temp.path().join("manifests");
+    share(temp.path(), "kept.bin", &[1u8; 64], 64);
+    let legacy = storage_dir.join("old");
+    std::fs::create_dir_all(legacy.join("nested")).unwrap();
+    std::fs::write(legacy.join("0.chunk"), [2u8; 64]).unwrap();
+    std::fs::write(legacy.join("nested/0.chunk"), [3u8; 64]).unwrap();
+    let notes = storage_dir.join("notes");
+    std::fs::create_dir_all(&notes).unwrap();
+    std::fs::write(notes.join("todo.md"), "keep me").unwrap();
+    // Strays in the object store: a file beside the buckets and a
+    // directory inside one stay; a leftover temporary file goes.
+    let objects = storage_dir.join("objects");
+    std::fs::write(objects.join("README"), "keep me").unwrap();
+    let bucket = std::fs::read_dir(&objects)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.is_dir())
+        .unwrap();
+    std::fs::create_dir(bucket.join("nested")).unwrap();
+    std::fs::write(bucket.join(".tmpA1b2C3"), [4u8; 8]).unwrap();
+
+    let dry = collect_garbage(&storage_dir, &manifest_dir, DRY_RUN).unwrap();
+    let mut expected = vec![legacy.join("0.chunk"), bucket.join(".tmpA1b2C3")];
+    let mut removed = dry.removed.clone();
+    expected.sort();
+    removed.sort();
+    assert_eq!(removed, expected);
+    let report = collect_garbage(&storage_dir, &manifest_dir, GcOptions::default()).unwrap();
+    assert_eq!(report.removed, dry.removed);
+    assert!(legacy.join("nested/0.chunk").exists());
+    assert!(notes.join("todo.md").exists());
+    assert!(objects.join("README").exists());
+    assert!(bucket.join("nested").is_dir());
+}
+
+#[test]
+fn gc_refuses_a_missing_or_empty_manifest_dir() {
+    let temp = tempfile::tempdir().unwrap();
+    let storage_dir = temp.path().join("chunks");
+    let manifest_dir = temp.path().join("manifests");
+    let dropped = share(temp.path(), "dropped.bin", &[1u8; 64], 64);
+
+    let missing = temp.path().join("typo");
+    assert!(collect_garbage(&storage_dir, &missing, DRY_RUN).is_err());
+    assert!(collect_garbage(&storage_dir, &missing, GcOptions::default()).is_err());
+
+    std::fs::remove_file(manifest_dir.join("dropped.bin.manifest.json")).unwrap();
+    let dry = collect_garbage(&storage_dir, &manifest_dir, DRY_RUN).unwrap();
+    assert_eq!(dry.removed.len(), 1);
+    assert!(collect_garbage(&storage_dir, &manifest_dir, GcOptions::default()).is_err());
+    assert!(storage::has_object(&storage_dir, &dropped.chunks[0]));
+
+    let options = GcOptions {
+        dry_run: false,
+        allow_empty: true,
+    };
+    let report = collect_garbage(&storage_dir, &manifest_dir, options).unwrap();
+    assert_eq!(report.removed, dry.removed);
+    assert!(!storage::has_object(&storage_dir, &dropped.chunks[0]));
+}
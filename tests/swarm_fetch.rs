@@ -0,0 +1,366 @@
+mod common;
+
+use common::{serve_config, share, spawn_server};
+use p2rent::bitfield::Bitfield;
+use p2rent::chunk::split_file;
+use p2rent::crypto::generate_keypair;
+use p2rent::manifest::{self, Manifest};
+use p2rent::net::download::{Downloader, query_availability};
+use p2rent::net::protocol::Message;
+use p2rent::net::quic::{self, QuicClient, QuicServer};
+use p2rent::storage;
+use p2rent::sync::{ShareOptions, sync_directory};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How a scripted peer answers one request.
+enum Reply {
+    Send(Message),
+    /// Closes the stream without an answer.
+    Drop,
+    /// Keeps the stream open and never answers.
+    Stall,
+}
+
+/// A peer that answers every stream with `reply(request)`.
+async fn spawn_scripted_server<F>(reply: F) -> SocketAddr
+where
+    F: Fn(Message) -> Reply + Send + Sync + 'static,
+{
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let reply = Arc::new(reply);
+    tokio::spawn(async move {
+        while let Ok(peer) = server.accept_and_handshake().await {
+            let reply = reply.clone();
+            tokio::spawn(async move {
+                while let Ok((mut send, mut recv)) = peer.connection.accept_bi().await {
+                    let Ok(request) = quic::receive_message(&mut recv).await else {
+                        continue;
+                    };
+                    match reply(request) {
+                        Reply::Send(msg) => {
+                            let _ = quic::send_message(&mut send, &msg).await;
+                        }
+                        Reply::Drop => {}
+                        Reply::Stall => {
+                            tokio::spawn(async move {
+                                let _open = (send, recv);
+                                std::future::pending::<()>().await
+                            });
+                        }
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// Claims every chunk and answers by-hash requests, flipping a byte in every
+/// chunk whose digest starts with an odd byte.
+async fn spawn_corrupt_server(storage_dir: PathBuf) -> SocketAddr {
+    spawn_scripted_server(move |request| match request {
+        Message::Need { share, chunks } => Reply::Send(Message::Have {
+            share,
+            chunks: Bitfield::full(chunks.len()),
+        }),
+        Message::RequestChunkByHash { hash } => match storage::load_object(&storage_dir, &hash) {
+            Ok(mut data) => {
+                if hash[0] % 2 == 1 {
+                    data[0] ^= 0xff;
+                }
+                Reply::Send(Message::ChunkByHash { hash, data })
+            }
+            Err(_) => Reply::Drop,
+        },
+        _ => Reply::Drop,
+    })
+    .await
+}
+
+#[tokio::test]
+async fn swarm_download_skips_corrupt_peer() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = (0..4096 + 77).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let manifest = share(temp.path(), "data.bin", &data, 256);
+
+    let good = spawn_server(serve_config(temp.path())).await;
+    let bad = spawn_corrupt_server(temp.path().join("chunks")).await;
+
+    let keypair = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let peers = vec![
+        client.connect_and_handshake(bad, &keypair).await.unwrap(),
+        client.connect_and_handshake(good, &keypair).await.unwrap(),
+    ];
+
+    let mut received = vec![None; manifest.chunks.len()];
+    Downloader::new(peers, manifest.share.clone(), &manifest)
+        .run(|index, bytes| {
+            received[index as usize] = Some(bytes);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+    let assembled: Vec<u8> = received.into_iter().flat_map(Option::unwrap).collect();
+    assert_eq!(assembled, data);
+}
+
+/// Claims every chunk and answers by-hash requests after `delay`, recording
+/// the most requests it was ever answering at once in `peak`. Slice
+/// requests are dropped so the downloader falls back to by-hash requests.
+async fn spawn_slow_server(
+    storage_dir: PathBuf,
+    delay: Duration,
+    peak: Arc<AtomicUsize>,
+) -> SocketAddr {
+    let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), generate_keypair().unwrap())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let active = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(async move {
+        while let Ok(peer) = server.accept_and_handshake().await {
+            while let Ok((mut send, mut recv)) = peer.connection.accept_bi().await {
+                let (storage_dir, active, peak) =
+                    (storage_dir.clone(), active.clone(), peak.clone());
+                tokio::spawn(async move {
+                    let reply = match quic::receive_message(&mut recv).await {
+                        Ok(Message::Need { share, chunks }) => Message::Have {
+                            share,
+                            chunks: Bitfield::full(chunks.len()),
+                        },
+                        Ok(Message::RequestChunkByHash { hash }) => {
+                            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            tokio::time::sleep(delay).await;
+                            active.fetch_sub(1, Ordering::SeqCst);
+                            let data = storage::load_object(&storage_dir, &hash).unwrap();
+                            Message::ChunkByHash { hash, data }
+                        }
+                        _ => return,
+                    };
+                    let _ = quic::send_message(&mut send, &reply).await;
+                });
+            }
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn pipelined_download_from_one_peer() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = (0..64 * 40 + 5)
+        .map(|i| (i % 241) as u8)
+        .collect::<Vec<_>>();
+    let manifest = share(temp.path(), "many.bin", &data, 64);
+
+    for window in [1, 8] {
+        let peak = Arc::new(AtomicUsize::new(0));
+        let delay = Duration::from_millis(20);
+        let addr = spawn_slow_server(temp.path().join("chunks"), delay, peak.clone()).await;
+        let keypair = generate_keypair().unwrap();
+        let client = QuicClient::new().await.unwrap();
+        let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+
+        let mut received = vec![None; manifest.chunks.len()];
+        Downloader::new(vec![peer], manifest.share.clone(), &manifest)
+            .window(window)
+            .run(|index, bytes| {
+                received[index as usize] = Some(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let assembled: Vec<u8> = received.into_iter().flat_map(Option::unwrap).collect();
+        assert_eq!(assembled, data);
+        assert_eq!(peak.load(Ordering::SeqCst), window);
+    }
+}
+
+#[tokio::test]
+async fn partial_seeders_cover_the_file_together() {
+    let temp = tempfile::tempdir().unwrap();
+    let file_path = temp.path().join("split.bin");
+    let data = (0..128 * 9 + 3)
+        .map(|i| (i % 239) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(&file_path, &data).unwrap();
+    let chunks = split_file(&file_path, 128).unwrap();
+    let manifest = Manifest::from_chunks("split.bin".into(), 128, &chunks);
+
+    // Each seeder has the manifest but only every other chunk.
+    let mut addrs = Vec::new();
+    for parity in 0..2 {
+        let seed = temp.path().join(format!("seed{parity}"));
+        for c in chunks.iter().filter(|c| c.index % 2 == parity) {
+            storage::save_object(&seed.join("chunks"), c).unwrap();
+        }
+        manifest::write_manifest(&manifest, &seed.join("manifests/split.bin.manifest.json"))
+            .unwrap();
+        addrs.push(spawn_server(serve_config(&seed)).await);
+    }
+
+    let keypair = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let mut peers = Vec::new();
+    for addr in addrs {
+        let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+        let has = query_availability(&peer, "split.bin", &Bitfield::full(chunks.len() as u64))
+            .await
+            .unwrap();
+        assert!(has.count_ones() < chunks.len() as u64);
+        peers.push(peer);
+    }
+
+    let mut received = vec![None; manifest.chunks.len()];
+    Downloader::new(peers, "split.bin".into(), &manifest)
+        .run(|index, bytes| {
+            received[index as usize] = Some(bytes);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+    let assembled: Vec<u8> = received.into_iter().flat_map(Option::unwrap).collect();
+    assert_eq!(assembled, data);
+}
+
+#[tokio::test]
+async fn same_named_files_in_a_directory_stay_apart() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("docs");
+    let contents = [
+        ("a/report.txt", vec![b'a'; 300]),
+        ("b/report.txt", vec![b'b'; 200]),
+        ("b/report.pdf", vec![b'p'; 100]),
+    ];
+    for (rel, data) in &contents {
+        let path = root.join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+    }
+    let (storage_dir, manifest_dir) = (temp.path().join("chunks"), temp.path().join("manifests"));
+    let tree = sync_directory(&root, &ShareOptions::new(64, &storage_dir, &manifest_dir)).unwrap();
+    assert_eq!(tree.files.len(), contents.len());
+
+    let addr = spawn_server(serve_config(temp.path())).await;
+    let keypair = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    for (rel, data) in &contents {
+        let share = format!("docs/{rel}");
+        let path = manifest::manifest_path(&temp.path().join("manifests"), &share);
+        let manifest = manifest::read_manifest(&path).unwrap();
+        assert_eq!(manifest.share, share);
+
+        let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+        let need = Bitfield::full(manifest.chunks.len() as u64);
+        let has = query_availability(&peer, &share, &need).await.unwrap();
+        assert!(has.is_full());
+
+        let mut received = vec![None; manifest.chunks.len()];
+        Downloader::new(vec![peer], share, &manifest)
+            .run(|index, bytes| {
+                received[index as usize] = Some(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let assembled: Vec<u8> = received.into_iter().flat_map(Option::unwrap).collect();
+        assert_eq!(&assembled, data);
+    }
+}
+
+#[tokio::test]
+async fn stalled_peer_does_not_hold_chunks_forever() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = (0..256 * 40).map(|i| (i % 233) as u8).collect::<Vec<_>>();
+    let manifest = share(temp.path(), "slow.bin", &data, 256);
+
+    // Claims every chunk, then never answers a request for one.
+    let stalled = || {
+        spawn_scripted_server(|request| match request {
+            Message::Need { share, chunks } => Reply::Send(Message::Have {
+                share,
+                chunks: Bitfield::full(chunks.len()),
+            }),
+            _ => Reply::Stall,
+        })
+    };
+    let good = spawn_server(serve_config(temp.path())).await;
+
+    let keypair = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let peers = vec![
+        client
+            .connect_and_handshake(stalled().await, &keypair)
+            .await
+            .unwrap(),
+        client.connect_and_handshake(good, &keypair).await.unwrap(),
+    ];
+    let mut received = vec![None; manifest.chunks.len()];
+    let download = Downloader::new(peers, manifest.share.clone(), &manifest)
+        .timeout(Duration::from_millis(300))
+        .run(|index, bytes| {
+            received[index as usize] = Some(bytes);
+            Ok(())
+        });
+    tokio::time::timeout(Duration::from_secs(10), download)
+        .await
+        .expect("download hung on the stalled peer")
+        .unwrap();
+    let assembled: Vec<u8> = received.into_iter().flat_map(Option::unwrap).collect();
+    assert_eq!(assembled, data);
+
+    // On its own the stalled peer is given up on rather than waited for.
+    let peer = client
+        .connect_and_handshake(stalled().await, &keypair)
+        .await
+        .unwrap();
+    let download = Downloader::new(vec![peer], manifest.share.clone(), &manifest)
+        .timeout(Duration::from_millis(300))
+        .run(|_, _| Ok(()));
+    let result = tokio::time::timeout(Duration::from_secs(10), download)
+        .await
+        .expect("download hung on the stalled peer");
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn peer_that_does_not_answer_need_is_asked_for_nothing() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = vec![7u8; 1000];
+    let manifest = share(temp.path(), "quiet.bin", &data, 100);
+
+    let storage_dir = temp.path().join("chunks");
+    let addr = spawn_scripted_server(move |request| match request {
+        Message::RequestChunkByHash { hash } => match storage::load_object(&storage_dir, &hash) {
+            Ok(data) => Reply::Send(Message::ChunkByHash { hash, data }),
+            Err(_) => Reply::Drop,
+        },
+        _ => Reply::Drop,
+    })
+    .await;
+
+    let keypair = generate_keypair().unwrap();
+    let client = QuicClient::new().await.unwrap();
+    let peer = client.connect_and_handshake(addr, &keypair).await.unwrap();
+    let mut received = 0;
+    let result = Downloader::new(vec![peer], manifest.share.clone(), &manifest)
+        .run(|_, _| {
+            received += 1;
+            Ok(())
+        })
+        .await;
+    assert!(result.is_err());
+    assert_eq!(received, 0);
+}